@@ -0,0 +1,40 @@
+use chip8_emulator::{CpuBuilder, Instruction, Quirks, RomBuffer};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+///Runs pong for a million instructions. The super-chip quirks keep sprites from waiting for a
+///vblank that never comes, so every call to cycle executes an instruction.
+fn run_cycles(c: &mut Criterion) {
+    let rom = include_bytes!("../pong.ch8").to_vec();
+    for (name, decode_cache) in [
+        ("run_cycles(1_000_000)", false),
+        ("run_cycles(1_000_000) with decode cache", true),
+    ] {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                let mut cpu = CpuBuilder::new(RomBuffer::from_bytes(rom.clone()).unwrap())
+                    .quirks(Quirks::superchip())
+                    .seed(0)
+                    .build();
+                cpu.set_decode_cache(decode_cache);
+                for _ in 0..1_000_000 {
+                    cpu.cycle().unwrap();
+                }
+                black_box(cpu.cycle_count())
+            })
+        });
+    }
+}
+
+///Decodes every possible opcode, including the ones that don't decode to anything
+fn decode(c: &mut Criterion) {
+    c.bench_function("decode every opcode", |b| {
+        b.iter(|| {
+            (0..=u16::MAX)
+                .filter_map(|opcode| Instruction::decode(black_box(opcode)))
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, run_cycles, decode);
+criterion_main!(benches);
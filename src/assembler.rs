@@ -0,0 +1,572 @@
+//! A small assembler for Octo, the assembly language most modern chip8 programs and test roms
+//! are written in. It covers the common subset: labels, constants, aliases, the chip8 and
+//! super-chip statements, `if`/`loop` control flow and sprite data. Macros, `:calc` and the
+//! `<`/`>` comparisons aren't supported and are reported as errors.
+use crate::ROM_START_ADDRESS;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+///Why a program couldn't be assembled, with the line the problem is on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    pub line: usize,
+    pub message: String,
+}
+impl core::fmt::Display for OctoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+///Turns Octo source into the bytes of a rom starting at ROM_START_ADDRESS. Like Octo, the rom
+///starts with a jump to the `main` label.
+pub fn assemble(source: &str) -> Result<Vec<u8>, OctoError> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(number, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace()
+                .map(move |token| (token, number + 1))
+        })
+        .collect();
+    let mut assembler = Assembler {
+        tokens,
+        position: 0,
+        //room for the jump to main
+        rom: vec![0, 0],
+        labels: BTreeMap::new(),
+        constants: BTreeMap::new(),
+        aliases: BTreeMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        branches: Vec::new(),
+    };
+    while assembler.position < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+///A 12 bit address in the rom that is filled in once every label is known
+struct Fixup {
+    offset: usize,
+    label: String,
+    line: usize,
+}
+
+///A condition of `if` and `while`, which is assembled as a skip instruction
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    KeyPressed(u8),
+    KeyNotPressed(u8),
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+impl Condition {
+    ///The opcode skipping the next instruction when the condition holds
+    fn skip_if_true(&self) -> u16 {
+        match *self {
+            Condition::Equal(x, Operand::Byte(kk)) => 0x3000 | (x as u16) << 8 | kk as u16,
+            Condition::NotEqual(x, Operand::Byte(kk)) => 0x4000 | (x as u16) << 8 | kk as u16,
+            Condition::Equal(x, Operand::Register(y)) => 0x5000 | (x as u16) << 8 | (y as u16) << 4,
+            Condition::NotEqual(x, Operand::Register(y)) => {
+                0x9000 | (x as u16) << 8 | (y as u16) << 4
+            }
+            Condition::KeyPressed(x) => 0xE09E | (x as u16) << 8,
+            Condition::KeyNotPressed(x) => 0xE0A1 | (x as u16) << 8,
+        }
+    }
+
+    ///The opcode skipping the next instruction when the condition doesn't hold
+    fn skip_if_false(&self) -> u16 {
+        let opcode = self.skip_if_true();
+        match opcode >> 12 {
+            0x3 => opcode + 0x1000,
+            0x4 => opcode - 0x1000,
+            0x5 => opcode + 0x4000,
+            0x9 => opcode - 0x4000,
+            //ex9e and exa1
+            _ => opcode ^ 0x003F,
+        }
+    }
+}
+
+struct Assembler<'a> {
+    ///every token of the source with its line number
+    tokens: Vec<(&'a str, usize)>,
+    position: usize,
+    rom: Vec<u8>,
+    labels: BTreeMap<&'a str, u16>,
+    constants: BTreeMap<&'a str, u16>,
+    aliases: BTreeMap<&'a str, u8>,
+    fixups: Vec<Fixup>,
+    ///for every open `loop`, its address and the jumps of its `while`s
+    loops: Vec<(u16, Vec<usize>)>,
+    ///for every open `if ... begin`, the jump to its `else` or `end`
+    branches: Vec<usize>,
+}
+
+impl<'a> Assembler<'a> {
+    ///The address the next byte ends up at
+    fn address(&self) -> u16 {
+        (ROM_START_ADDRESS + self.rom.len()) as u16
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position.saturating_sub(1))
+            .map_or(0, |&(_, line)| line)
+    }
+
+    fn error(&self, message: String) -> OctoError {
+        OctoError {
+            line: self.line(),
+            message,
+        }
+    }
+
+    fn next(&mut self) -> Result<&'a str, OctoError> {
+        match self.tokens.get(self.position) {
+            Some(&(token, _)) => {
+                self.position += 1;
+                Ok(token)
+            }
+            None => Err(self.error(String::from("unexpected end of program"))),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(self.error(format!("expected {}, found {}", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.rom.extend_from_slice(&opcode.to_be_bytes());
+    }
+
+    ///Emits an instruction with a 12 bit address, which may be a label defined further down
+    fn emit_with_address(&mut self, opcode: u16, target: &'a str) -> Result<(), OctoError> {
+        let offset = self.rom.len();
+        let address = match self.number(target)? {
+            Some(address) => address,
+            None => match self.labels.get(target) {
+                Some(&address) => address,
+                None => {
+                    self.fixups.push(Fixup {
+                        offset,
+                        label: target.to_string(),
+                        line: self.line(),
+                    });
+                    0
+                }
+            },
+        };
+        if address > 0xFFF {
+            return Err(self.error(format!("{} is not a 12 bit address", target)));
+        }
+        self.emit(opcode | address);
+        Ok(())
+    }
+
+    ///A jump whose address is filled in later with patch, returns where it is in the rom
+    fn emit_placeholder_jump(&mut self) -> usize {
+        let offset = self.rom.len();
+        self.emit(0x1000);
+        offset
+    }
+
+    fn patch(&mut self, offset: usize, address: u16) {
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+    }
+
+    ///A number literal or a constant, None when the token is neither
+    fn number(&self, token: &str) -> Result<Option<u16>, OctoError> {
+        if let Some(&value) = self.constants.get(token) {
+            return Ok(Some(value));
+        }
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16)
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            u16::from_str_radix(binary, 2)
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse()
+        } else {
+            return Ok(None);
+        };
+        let value = parsed.map_err(|_| self.error(format!("{} is not a valid number", token)))?;
+        Ok(Some(if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        }))
+    }
+
+    ///A value that fits in a byte, negative numbers are stored as their two's complement
+    fn byte(&self, token: &str) -> Result<u8, OctoError> {
+        match self.number(token)? {
+            Some(value) if value <= 0xFF || value >= 0xFF80 => Ok(value as u8),
+            Some(_) => Err(self.error(format!("{} does not fit in a byte", token))),
+            None => Err(self.error(format!("expected a number, found {}", token))),
+        }
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn expect_register(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        self.register(token)
+            .ok_or_else(|| self.error(format!("expected a register, found {}", token)))
+    }
+
+    fn operand(&mut self) -> Result<Operand, OctoError> {
+        let token = self.next()?;
+        match self.register(token) {
+            Some(register) => Ok(Operand::Register(register)),
+            None => Ok(Operand::Byte(self.byte(token)?)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError> {
+        let x = self.expect_register()?;
+        match self.next()? {
+            "==" => Ok(Condition::Equal(x, self.operand()?)),
+            "!=" => Ok(Condition::NotEqual(x, self.operand()?)),
+            "key" => Ok(Condition::KeyPressed(x)),
+            "-key" => Ok(Condition::KeyNotPressed(x)),
+            other => Err(self.error(format!("unsupported comparison {}", other))),
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        let token = self.next()?;
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name, self.address()).is_some() {
+                    return Err(self.error(format!("label {} is defined twice", name)));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let token = self.next()?;
+                let value = self
+                    .number(token)?
+                    .or_else(|| self.labels.get(token).copied())
+                    .ok_or_else(|| self.error(format!("expected a number, found {}", token)))?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.expect_register()?;
+                self.aliases.insert(name, register);
+            }
+            ":byte" => {
+                let token = self.next()?;
+                let value = self.byte(token)?;
+                self.rom.push(value);
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_with_address(0x2000, target)?;
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-down" => {
+                let token = self.next()?;
+                let n = self.byte(token)?;
+                if n > 0xF {
+                    return Err(self.error(format!("can scroll at most 15 rows, not {}", n)));
+                }
+                self.emit(0x00C0 | n as u16);
+            }
+            "scroll-up" => {
+                let token = self.next()?;
+                let n = self.byte(token)?;
+                if n > 0xF {
+                    return Err(self.error(format!("can scroll at most 15 rows, not {}", n)));
+                }
+                self.emit(0x00D0 | n as u16);
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_with_address(0x1000, target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_with_address(0xB000, target)?;
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let token = self.next()?;
+                let n = self.byte(token)?;
+                if n > 0xF {
+                    return Err(self.error(format!("sprites are at most 15 rows, not {}", n)));
+                }
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n as u16);
+            }
+            "bcd" => self.register_statement(0xF033)?,
+            "save" => self.register_statement(0xF055)?,
+            "load" => self.register_statement(0xF065)?,
+            "saveflags" => self.register_statement(0xF075)?,
+            "loadflags" => self.register_statement(0xF085)?,
+            "delay" => {
+                self.expect(":=")?;
+                self.register_statement(0xF015)?;
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                self.register_statement(0xF018)?;
+            }
+            "audio" => self.emit(0xF002),
+            "pitch" => {
+                self.expect(":=")?;
+                self.register_statement(0xF03A)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()? {
+                    "then" => self.emit(condition.skip_if_false()),
+                    "begin" => {
+                        self.emit(condition.skip_if_true());
+                        let jump = self.emit_placeholder_jump();
+                        self.branches.push(jump);
+                    }
+                    other => {
+                        return Err(self.error(format!("expected then or begin, found {}", other)))
+                    }
+                }
+            }
+            "else" => {
+                let to_else = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error(String::from("else without if ... begin")))?;
+                let to_end = self.emit_placeholder_jump();
+                let address = self.address();
+                self.patch(to_else, address);
+                self.branches.push(to_end);
+            }
+            "end" => {
+                let jump = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error(String::from("end without if ... begin")))?;
+                let address = self.address();
+                self.patch(jump, address);
+            }
+            "loop" => {
+                let address = self.address();
+                self.loops.push((address, Vec::new()));
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit(condition.skip_if_true());
+                let jump = self.emit_placeholder_jump();
+                match self.loops.last_mut() {
+                    Some((_, breaks)) => breaks.push(jump),
+                    None => return Err(self.error(String::from("while outside of a loop"))),
+                }
+            }
+            "again" => {
+                let (start, breaks) = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| self.error(String::from("again without loop")))?;
+                self.emit(0x1000 | start);
+                let address = self.address();
+                for jump in breaks {
+                    self.patch(jump, address);
+                }
+            }
+            _ => {
+                if let Some(x) = self.register(token) {
+                    self.register_assignment(x)?;
+                } else if self.number(token)?.is_some() {
+                    //sprite and other data
+                    let value = self.byte(token)?;
+                    self.rom.push(value);
+                } else if token.starts_with(':') {
+                    return Err(self.error(format!("unsupported directive {}", token)));
+                } else {
+                    //a bare label name calls it
+                    self.emit_with_address(0x2000, token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///A statement taking only a register, the opcode has the register at x
+    fn register_statement(&mut self, opcode: u16) -> Result<(), OctoError> {
+        let x = self.expect_register()?;
+        self.emit(opcode | (x as u16) << 8);
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), OctoError> {
+        match self.next()? {
+            ":=" => {
+                let token = self.next()?;
+                match token {
+                    "hex" => self.register_statement(0xF029),
+                    "bighex" => self.register_statement(0xF030),
+                    _ => self.emit_with_address(0xA000, token),
+                }
+            }
+            "+=" => self.register_statement(0xF01E),
+            other => Err(self.error(format!("unsupported operator i {}", other))),
+        }
+    }
+
+    fn register_assignment(&mut self, x: u8) -> Result<(), OctoError> {
+        let x_bits = (x as u16) << 8;
+        let operator = self.next()?;
+        if operator == ":=" {
+            let token = self.next()?;
+            match token {
+                "random" => {
+                    let token = self.next()?;
+                    let mask = self.byte(token)?;
+                    self.emit(0xC000 | x_bits | mask as u16);
+                }
+                "delay" => self.emit(0xF007 | x_bits),
+                "key" => self.emit(0xF00A | x_bits),
+                _ => match self.register(token) {
+                    Some(y) => self.emit(0x8000 | x_bits | (y as u16) << 4),
+                    None => {
+                        let kk = self.byte(token)?;
+                        self.emit(0x6000 | x_bits | kk as u16);
+                    }
+                },
+            }
+            return Ok(());
+        }
+
+        let operand = self.operand()?;
+        let alu = match operator {
+            "|=" => 0x1,
+            "&=" => 0x2,
+            "^=" => 0x3,
+            "+=" => 0x4,
+            "-=" => 0x5,
+            ">>=" => 0x6,
+            "=-" => 0x7,
+            "<<=" => 0xE,
+            other => return Err(self.error(format!("unsupported operator {}", other))),
+        };
+        match operand {
+            Operand::Register(y) => self.emit(0x8000 | x_bits | (y as u16) << 4 | alu),
+            Operand::Byte(kk) if operator == "+=" => self.emit(0x7000 | x_bits | kk as u16),
+            Operand::Byte(kk) if operator == "-=" => {
+                self.emit(0x7000 | x_bits | kk.wrapping_neg() as u16)
+            }
+            Operand::Byte(_) => {
+                return Err(self.error(format!("{} needs a register on the right", operator)))
+            }
+        }
+        Ok(())
+    }
+
+    ///Fills in the addresses of labels used before they were defined and the jump to main
+    fn finish(mut self) -> Result<Vec<u8>, OctoError> {
+        if !self.loops.is_empty() {
+            return Err(self.error(String::from("loop without again")));
+        }
+        if !self.branches.is_empty() {
+            return Err(self.error(String::from("if ... begin without end")));
+        }
+        for fixup in core::mem::take(&mut self.fixups) {
+            let address = *self
+                .labels
+                .get(fixup.label.as_str())
+                .ok_or_else(|| OctoError {
+                    line: fixup.line,
+                    message: format!("unknown label {}", fixup.label),
+                })?;
+            self.patch(fixup.offset, address);
+        }
+        let main = *self.labels.get("main").ok_or_else(|| OctoError {
+            line: 1,
+            message: String::from("the program has no main label"),
+        })?;
+        self.rom[0] = 0x10;
+        self.patch(0, main);
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_labels_constants_loops_and_sprites() {
+        let source = "
+            :const X 10
+            : main
+              clear
+              i := smile # used before it is defined
+              v0 := X
+              v1 := 5
+              loop
+                sprite v0 v1 4
+                v0 += 1
+                while v0 != 20
+              again
+            : smile
+              0x24 0x00 0x81 0x7E
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0x12, 0x02, 0x00, 0xE0, 0xA2, 0x14, 0x60, 0x0A, 0x61, 0x05, 0xD0, 0x14, 0x70, 0x01,
+                0x40, 0x14, 0x12, 0x14, 0x12, 0x0A, 0x24, 0x00, 0x81, 0x7E,
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_labels_are_reported_on_their_line() {
+        let error = assemble(": main\n  jump nowhere").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "unknown label nowhere");
+    }
+
+    #[test]
+    fn a_program_needs_a_main_label() {
+        assert!(assemble("clear").is_err());
+    }
+}
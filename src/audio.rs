@@ -0,0 +1,247 @@
+//! The beep played while the sound timer runs. The tone is generated as a short wav that loops,
+//! and is generated again whenever the settings change. Xo-chip programs can replace the beep
+//! with their own 1 bit sound pattern, which is turned into a looping wav the same way.
+use chip8_emulator::AUDIO_PATTERN_SIZE;
+use macroquad::audio::{load_sound_from_bytes, play_sound, stop_sound, PlaySoundParams, Sound};
+use std::f32::consts::TAU;
+
+///samples per second of the generated tone
+const SAMPLE_RATE: u32 = 44100;
+///roughly how long the looping tone lasts, it always holds a whole number of periods so the loop
+///doesn't click
+const TONE_LENGTH: f32 = 0.1;
+///bits per second of a sound pattern played at the default pitch of 64
+const PATTERN_RATE: f32 = 4000.0;
+///pitch in Hz of the blip played on sprite collisions, an octave above the default beep
+const BLIP_FREQUENCY: f32 = 880.0;
+///how long the collision blip lasts in seconds
+const BLIP_LENGTH: f32 = 0.03;
+
+///The shape of the tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+impl Waveform {
+    ///The next waveform, for cycling through them with a single key
+    pub fn next(self) -> Self {
+        match self {
+            Waveform::Square => Waveform::Sine,
+            Waveform::Sine => Waveform::Triangle,
+            Waveform::Triangle => Waveform::Square,
+        }
+    }
+
+    ///The height of the wave, between -1 and 1, at a phase from 0 to 1 through its period
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+///What the beep sounds like
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    ///pitch in Hz
+    pub frequency: f32,
+    ///from 0 for silent to 1 for full volume
+    pub volume: f32,
+    pub waveform: Waveform,
+    ///play a short blip whenever a sprite collides, off by default since no real machine did
+    pub collision_blip: bool,
+}
+impl Default for AudioConfig {
+    ///a square wave at a moderate volume, like the buzzer of the old machines
+    fn default() -> Self {
+        AudioConfig {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+            collision_blip: false,
+        }
+    }
+}
+
+///A sound pattern together with the pitch it plays at
+type Pattern = ([u8; AUDIO_PATTERN_SIZE], u8);
+
+///Plays the beep while the sound timer runs
+pub struct Beeper {
+    config: AudioConfig,
+    ///the program's sound pattern, played instead of the beep when there is one
+    pattern: Option<Pattern>,
+    sound: Option<Sound>,
+    ///the collision blip, in the same waveform as the beep
+    blip: Option<Sound>,
+    playing: bool,
+}
+impl Beeper {
+    pub async fn new(config: AudioConfig) -> Self {
+        Beeper {
+            config,
+            pattern: None,
+            sound: load_tone(&config, None).await,
+            blip: load_wav(&tone_wav(&blip_config(&config), BLIP_LENGTH)).await,
+            playing: false,
+        }
+    }
+
+    pub fn config(&self) -> AudioConfig {
+        self.config
+    }
+
+    ///Starts or stops the beep, call it every frame
+    pub fn update(&mut self, beeping: bool) {
+        if beeping == self.playing {
+            return;
+        }
+        self.playing = beeping;
+        if let Some(sound) = &self.sound {
+            if beeping {
+                play_sound(
+                    sound,
+                    PlaySoundParams {
+                        looped: true,
+                        volume: self.config.volume,
+                    },
+                );
+            } else {
+                stop_sound(sound);
+            }
+        }
+    }
+
+    ///Generates the tone again for the new settings, a beep that is playing carries on with them
+    pub async fn set_config(&mut self, config: AudioConfig) {
+        let playing = self.playing;
+        self.update(false);
+        self.config = config;
+        self.sound = load_tone(&config, self.pattern.as_ref()).await;
+        self.blip = load_wav(&tone_wav(&blip_config(&config), BLIP_LENGTH)).await;
+        self.update(playing);
+    }
+
+    ///Plays the collision blip once, when it's turned on
+    pub fn collision(&self) {
+        if !self.config.collision_blip {
+            return;
+        }
+        if let Some(blip) = &self.blip {
+            play_sound(
+                blip,
+                PlaySoundParams {
+                    looped: false,
+                    volume: self.config.volume,
+                },
+            );
+        }
+    }
+
+    ///Plays the program's sound pattern at the given pitch instead of the beep, or the beep again
+    ///for None. Call it every frame, the sound is only generated again when something changed.
+    pub async fn set_pattern(&mut self, pattern: Option<&[u8; AUDIO_PATTERN_SIZE]>, pitch: u8) {
+        let pattern = pattern.map(|pattern| (*pattern, pitch));
+        if pattern == self.pattern {
+            return;
+        }
+        let playing = self.playing;
+        self.update(false);
+        self.pattern = pattern;
+        self.sound = load_tone(&self.config, pattern.as_ref()).await;
+        self.update(playing);
+    }
+}
+
+async fn load_tone(config: &AudioConfig, pattern: Option<&Pattern>) -> Option<Sound> {
+    let wav = match pattern {
+        Some((pattern, pitch)) => pattern_wav(pattern, *pitch),
+        None => tone_wav(config, TONE_LENGTH),
+    };
+    load_wav(&wav).await
+}
+
+async fn load_wav(wav: &[u8]) -> Option<Sound> {
+    match load_sound_from_bytes(wav).await {
+        Ok(sound) => Some(sound),
+        Err(error) => {
+            eprintln!(
+                "could not create the beep, the emulator stays silent: {}",
+                error
+            );
+            None
+        }
+    }
+}
+
+///The settings of the collision blip, the beep's waveform at a higher pitch
+fn blip_config(config: &AudioConfig) -> AudioConfig {
+    AudioConfig {
+        frequency: BLIP_FREQUENCY,
+        ..*config
+    }
+}
+
+///A wav of the tone, roughly length seconds long but holding a whole number of its periods
+fn tone_wav(config: &AudioConfig, length: f32) -> Vec<u8> {
+    let frequency = config.frequency.max(1.0);
+    let periods = (frequency * length).round().max(1.0);
+    let samples = (periods / frequency * SAMPLE_RATE as f32).round() as u32;
+    wav((0..samples).map(|sample| {
+        let phase = (sample as f32 * periods / samples as f32).fract();
+        config.waveform.sample(phase)
+    }))
+}
+
+///A wav of one pass through the sound pattern, from the highest bit of the first byte on. Set bits
+///are high and cleared bits low, played at 4000 * 2^((pitch - 64) / 48) bits per second.
+fn pattern_wav(pattern: &[u8; AUDIO_PATTERN_SIZE], pitch: u8) -> Vec<u8> {
+    let bits = AUDIO_PATTERN_SIZE * 8;
+    let rate = PATTERN_RATE * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+    let samples = (bits as f32 / rate * SAMPLE_RATE as f32).round().max(1.0) as usize;
+    wav((0..samples).map(|sample| {
+        let bit = sample * bits / samples;
+        if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }))
+}
+
+///A 16 bit mono wav of samples between -1 and 1. The volume is applied when it's played, the wav
+///is at full scale.
+fn wav(samples: impl ExactSizeIterator<Item = f32>) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    //pcm, mono
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    //bytes per sample, bits per sample
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let value = sample * i16::MAX as f32;
+        wav.extend_from_slice(&(value as i16).to_le_bytes());
+    }
+    wav
+}
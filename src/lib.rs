@@ -0,0 +1,3957 @@
+//without the std feature only alloc is needed, so the core runs on embedded targets too
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use ::rand::rngs::StdRng;
+use ::rand::{Rng, SeedableRng};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+pub mod assembler;
+
+///The types most front-ends and debuggers need, `use chip8_emulator::prelude::*` brings them in
+///at once. The registers, stack and ram stay internal, the Cpu has accessors for reading them.
+pub mod prelude {
+    pub use crate::{
+        draw_framebuffer, until, Chip8Variant, Cpu, CpuBuilder, CpuError, CpuSnapshot,
+        FrameOutcome, Framebuffer, FramebufferSink, Instruction, InstructionKind, InvalidRegister,
+        OutOfBounds, Quirk, Quirks, RegionKind, RomBuffer, RomError, UnknownOpcode,
+        UnknownOpcodePolicy, WatchpointHit, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+    };
+}
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+///The size of the display in super-chip's high resolution mode, switched to with 00FF
+pub const HIGH_RES_DISPLAY_WIDTH: usize = 128;
+pub const HIGH_RES_DISPLAY_HEIGHT: usize = 64;
+pub const RAM_SIZE: usize = 4096; //in bytes :)
+pub const ROM_START_ADDRESS: usize = 0x200;
+///Where programs for the eti 660 start, see CpuBuilder::start_address
+pub const ETI_660_START_ADDRESS: usize = 0x600;
+pub const NUM_KEYS: usize = 16;
+///V0 through VF
+pub const NUM_REGISTERS: usize = 16;
+///Bytes in xo-chip's sound pattern, see Cpu::audio_pattern
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+///The pitch the sound pattern plays at until fx3a changes it
+const DEFAULT_PITCH: u8 = 64;
+///How deep subroutines can be nested
+pub const STACK_SIZE: usize = 16;
+///How many times per second the timers count down by default, see CpuBuilder::refresh_rate
+pub const REFRESH_RATE: u32 = 60;
+///How long a frame of the timers lasts at the default refresh rate
+pub const FRAME_DURATION: Duration = frame_duration(REFRESH_RATE);
+///How long a frame lasts at a refresh rate in Hz
+const fn frame_duration(refresh_rate: u32) -> Duration {
+    Duration::from_nanos(1_000_000_000 / refresh_rate as u64)
+}
+///The longest stretch of time Cpu::step_for catches up on, so a stall doesn't make it race ahead
+const MAX_STEP: Duration = Duration::from_millis(250);
+
+///The hexadecimal keypad as laid out on the cosmac vip, row by row
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+///The label printed on a key of the keypad, which is its value as a hexadecimal digit
+pub fn key_label(key: u8) -> char {
+    core::char::from_digit(key as u32 & 0xf, 16)
+        .unwrap_or('?')
+        .to_ascii_uppercase()
+}
+
+///Most planes a framebuffer can have, every pixel keeps its planes as the bits of a byte
+pub const MAX_PLANES: u8 = 8;
+
+///The display. Every pixel is a bitmask with a bit for each plane it is lit on, monochrome
+///roms only ever draw to plane 0. It is DISPLAY_WIDTH by DISPLAY_HEIGHT pixels, or
+///HIGH_RES_DISPLAY_WIDTH by HIGH_RES_DISPLAY_HEIGHT in high resolution, pixels are only valid
+///within its current size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pixels: [[u8; HIGH_RES_DISPLAY_WIDTH]; HIGH_RES_DISPLAY_HEIGHT],
+    planes: u8,
+    high_resolution: bool,
+}
+impl Framebuffer {
+    ///A blank monochrome display in low resolution
+    pub fn new() -> Self {
+        Framebuffer::with_planes(1)
+    }
+
+    ///A blank low resolution display with the given number of planes, at most MAX_PLANES
+    pub fn with_planes(planes: u8) -> Self {
+        assert!(
+            (1..=MAX_PLANES).contains(&planes),
+            "a framebuffer has 1 to {} planes, not {}",
+            MAX_PLANES,
+            planes
+        );
+        Framebuffer {
+            pixels: [[0; HIGH_RES_DISPLAY_WIDTH]; HIGH_RES_DISPLAY_HEIGHT],
+            planes,
+            high_resolution: false,
+        }
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn width(&self) -> usize {
+        if self.high_resolution {
+            HIGH_RES_DISPLAY_WIDTH
+        } else {
+            DISPLAY_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.high_resolution {
+            HIGH_RES_DISPLAY_HEIGHT
+        } else {
+            DISPLAY_HEIGHT
+        }
+    }
+
+    pub fn is_high_resolution(&self) -> bool {
+        self.high_resolution
+    }
+
+    ///Switches between low and high resolution, which blanks the display like 00FE and 00FF do
+    pub fn set_high_resolution(&mut self, high_resolution: bool) {
+        self.high_resolution = high_resolution;
+        self.clear();
+    }
+
+    ///The planes the pixel is lit on, bit n stands for plane n
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y][x]
+    }
+
+    ///Turns the pixel on or off on a single plane, leaving its other planes alone
+    pub fn set(&mut self, x: usize, y: usize, plane: u8, on: bool) {
+        assert!(
+            plane < self.planes,
+            "plane {} is past the {} planes of the framebuffer",
+            plane,
+            self.planes
+        );
+        let bit = 1 << plane;
+        if on {
+            self.pixels[y][x] |= bit;
+        } else {
+            self.pixels[y][x] &= !bit;
+        }
+    }
+
+    ///Whether the pixel is lit on any plane, which is all the monochrome path cares about
+    pub fn is_on(&self, x: usize, y: usize) -> bool {
+        self.pixels[y][x] != 0
+    }
+
+    ///Whether no pixel is lit on any plane
+    pub fn is_blank(&self) -> bool {
+        self.pixels().all(|pixel| pixel == 0)
+    }
+
+    ///Every pixel's planes, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = u8> + '_ {
+        let width = self.width();
+        self.pixels[..self.height()]
+            .iter()
+            .flat_map(move |row| row[..width].iter().copied())
+    }
+
+    ///Turns every pixel off on every plane
+    pub fn clear(&mut self) {
+        self.pixels = [[0; HIGH_RES_DISPLAY_WIDTH]; HIGH_RES_DISPLAY_HEIGHT];
+    }
+
+    ///Moves everything down by n rows, the rows scrolled in at the top are blank
+    pub fn scroll_down(&mut self, n: usize) {
+        for row in (0..self.height()).rev() {
+            self.pixels[row] = if row >= n {
+                self.pixels[row - n]
+            } else {
+                [0; HIGH_RES_DISPLAY_WIDTH]
+            };
+        }
+    }
+
+    ///Moves everything up by n rows, the rows scrolled in at the bottom are blank
+    pub fn scroll_up(&mut self, n: usize) {
+        let height = self.height();
+        for row in 0..height {
+            self.pixels[row] = if row + n < height {
+                self.pixels[row + n]
+            } else {
+                [0; HIGH_RES_DISPLAY_WIDTH]
+            };
+        }
+    }
+
+    ///Moves everything right by n columns, the columns scrolled in on the left are blank
+    pub fn scroll_right(&mut self, n: usize) {
+        let width = self.width();
+        for row in self.pixels.iter_mut() {
+            row.copy_within(..width - n, n);
+            row[..n].fill(0);
+        }
+    }
+
+    ///Moves everything left by n columns, the columns scrolled in on the right are blank
+    pub fn scroll_left(&mut self, n: usize) {
+        let width = self.width();
+        for row in self.pixels.iter_mut() {
+            row.copy_within(n..width, 0);
+            row[width - n..width].fill(0);
+        }
+    }
+}
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+///Draws the framebuffer as text, a block for every lit pixel and one line per row
+pub fn render_ascii(fb: &Framebuffer) -> String {
+    let mut text = String::with_capacity((fb.width() * 3 + 1) * fb.height());
+    for y in 0..fb.height() {
+        text.extend((0..fb.width()).map(|x| if fb.is_on(x, y) { '█' } else { ' ' }));
+        text.push('\n');
+    }
+    text
+}
+
+///Formats bytes as a hex dump, 16 to a line, each line starting with the address of its first
+///byte and ending with the bytes as ascii, where unprintable bytes show as dots
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        text.push_str(&format!(
+            "{:04X}: {:<47}  |{}|\n",
+            line * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    text
+}
+
+///Anything the framebuffer can be drawn onto, so every front-end draws it the same way
+pub trait FramebufferSink {
+    fn set_pixel(&mut self, x: usize, y: usize, on: bool);
+}
+
+///Draws every pixel of the framebuffer onto the sink, row by row
+pub fn draw_framebuffer(fb: &Framebuffer, sink: &mut impl FramebufferSink) {
+    for y in 0..fb.height() {
+        for x in 0..fb.width() {
+            sink.set_pixel(x, y, fb.is_on(x, y));
+        }
+    }
+}
+
+///Rgba bytes, row by row, at the display's native resolution
+pub struct RgbaSink {
+    pub pixels: Vec<u8>,
+    width: usize,
+    fg: [u8; 4],
+    bg: [u8; 4],
+}
+impl RgbaSink {
+    ///A sink for a low resolution display
+    pub fn new(fg: [u8; 4], bg: [u8; 4]) -> Self {
+        RgbaSink::with_size(DISPLAY_WIDTH, DISPLAY_HEIGHT, fg, bg)
+    }
+
+    pub fn with_size(width: usize, height: usize, fg: [u8; 4], bg: [u8; 4]) -> Self {
+        RgbaSink {
+            pixels: vec![0; width * height * 4],
+            width,
+            fg,
+            bg,
+        }
+    }
+}
+impl FramebufferSink for RgbaSink {
+    fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let offset = (y * self.width + x) * 4;
+        let color = if on { self.fg } else { self.bg };
+        self.pixels[offset..offset + 4].copy_from_slice(&color);
+    }
+}
+
+///Converts the framebuffer into rgba bytes, row by row, at its native resolution
+pub fn framebuffer_to_rgba(fb: &Framebuffer, fg: [u8; 4], bg: [u8; 4]) -> Vec<u8> {
+    let mut sink = RgbaSink::with_size(fb.width(), fb.height(), fg, bg);
+    draw_framebuffer(fb, &mut sink);
+    sink.pixels
+}
+
+///An address past the end of ram was read or written, contains the address
+#[derive(Debug, Clone, Copy)]
+pub struct OutOfBounds(pub usize);
+impl core::fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "address {:#05X} is outside of ram", self.0)
+    }
+}
+
+///Why a running program had to stop
+#[derive(Debug, Clone, Copy)]
+pub enum CpuError {
+    ///an address past the end of ram was read or written
+    OutOfBounds(OutOfBounds),
+    ///00EE ran without a subroutine to return from, contains the address of the 00EE
+    StackUnderflow(u16),
+    ///2NNN ran with every level of the stack in use, contains the address of the 2NNN
+    StackOverflow(u16),
+}
+impl From<OutOfBounds> for CpuError {
+    fn from(out_of_bounds: OutOfBounds) -> Self {
+        CpuError::OutOfBounds(out_of_bounds)
+    }
+}
+impl core::fmt::Display for CpuError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            CpuError::OutOfBounds(out_of_bounds) => write!(f, "{}", out_of_bounds),
+            CpuError::StackUnderflow(address) => write!(
+                f,
+                "{:04X} returned from a subroutine while none was running",
+                address
+            ),
+            CpuError::StackOverflow(address) => write!(
+                f,
+                "{:04X} called a subroutine nested more than {} levels deep",
+                address, STACK_SIZE
+            ),
+        }
+    }
+}
+
+///A register index past VF was used, contains the index
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidRegister(pub u8);
+impl core::fmt::Display for InvalidRegister {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "there is no register V{:X}, the last one is VF", self.0)
+    }
+}
+
+///Size of the font, 5 bytes for each of the 16 hexadecimal digits
+pub const FONT_SIZE: usize = 16 * 5;
+///Where the large font starts, right after the regular one
+const LARGE_FONT_ADDRESS: usize = FONT_SIZE;
+
+///The 4 by 5 pixel digits, pointed at by fx29
+const FONT: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, //0
+    0x20, 0x60, 0x20, 0x20, 0x70, //1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, //2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, //3
+    0x90, 0x90, 0xF0, 0x10, 0x10, //4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, //5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, //6
+    0xF0, 0x10, 0x20, 0x40, 0x40, //7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, //8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, //9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, //a
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, //b
+    0xF0, 0x80, 0x80, 0x80, 0xF0, //c
+    0xE0, 0x90, 0x90, 0x90, 0xE0, //d
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, //e
+    0xF0, 0x80, 0xF0, 0x80, 0x80, //f
+];
+
+///The super-chip's 8 by 10 pixel digits, pointed at by fx30
+const LARGE_FONT: [u8; 16 * 10] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, //0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, //1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, //4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, //6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, //7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, //8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, //9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, //a
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, //b
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, //c
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, //d
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //e
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, //f
+];
+
+///The ram of the chip8 cpu, uses big endian, and is laid out in the following way:
+///0x000 start of chip-8 ram
+///0x000 to 0x050 reserved for fontset
+///0x050 to 0x0F0 reserved for the large super-chip fontset
+///0x200 start of most chip-8 programs
+///0x600 start of eti 660 chip8 programs
+///0xfff end of chip8 ram
+#[derive(Debug, Clone)]
+struct Ram {
+    bytes: [u8; RAM_SIZE],
+}
+impl Ram {
+    fn with_fonts() -> Self {
+        Self::with_custom_fonts(&FONT)
+    }
+
+    ///Loads the font instead of the usual one, only its first FONT_SIZE bytes are used. The
+    ///large super-chip font is always the default one.
+    fn with_custom_fonts(font: &[u8]) -> Self {
+        let mut ram = Self {
+            bytes: [0; RAM_SIZE],
+        };
+
+        let font = &font[..font.len().min(FONT_SIZE)];
+        ram.bytes[..font.len()].copy_from_slice(font);
+        ram.bytes[LARGE_FONT_ADDRESS..LARGE_FONT_ADDRESS + LARGE_FONT.len()]
+            .copy_from_slice(&LARGE_FONT);
+        ram
+    }
+
+    ///returns the two bytes at index as an opcode, or an error when either lies past the end of ram
+    fn get(&self, index: u16) -> Result<u16, OutOfBounds> {
+        let index = index as usize;
+        let high = self.try_get_byte(index)? as u16;
+        let low = self.try_get_byte(index + 1)? as u16;
+        Ok(high << 8 | low)
+    }
+
+    ///returns a single byte from ram, or an error when the index lies past the end of ram
+    fn try_get_byte(&self, index: usize) -> Result<u8, OutOfBounds> {
+        self.bytes.get(index).copied().ok_or(OutOfBounds(index))
+    }
+
+    ///writes a single byte to ram, or returns an error when the index lies past the end of ram
+    fn try_set(&mut self, index: usize, value: u8) -> Result<(), OutOfBounds> {
+        let byte = self.bytes.get_mut(index).ok_or(OutOfBounds(index))?;
+        *byte = value;
+        Ok(())
+    }
+}
+
+///Everything that can go wrong while turning a file into a loadable rom
+#[derive(Debug)]
+pub enum RomError {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    ///the rom does not fit in the memory after ROM_START_ADDRESS, contains the size in bytes
+    TooLarge(usize),
+    ///the file is not a chip8 rom, for instance because of its extension
+    NotARom(String),
+    ///the octo source could not be assembled
+    Octo(assembler::OctoError),
+}
+impl core::fmt::Display for RomError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            RomError::Io(error) => write!(f, "could not read rom: {}", error),
+            RomError::TooLarge(size) => write!(
+                f,
+                "rom is {} bytes, but at most {} bytes fit in memory",
+                size,
+                RAM_SIZE - ROM_START_ADDRESS
+            ),
+            RomError::NotARom(name) => write!(f, "{} does not look like a chip8 rom", name),
+            RomError::Octo(error) => write!(f, "could not assemble rom: {}", error),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl From<std::io::Error> for RomError {
+    fn from(error: std::io::Error) -> Self {
+        RomError::Io(error)
+    }
+}
+
+///The chip8 dialects a rom can be written for, each one extends the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chip8Variant {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+impl Chip8Variant {
+    ///A speed that roms written for the dialect usually expect
+    pub fn instructions_per_second(self) -> u32 {
+        match self {
+            Chip8Variant::Chip8 => 500,
+            Chip8Variant::SuperChip | Chip8Variant::XoChip => 1000,
+        }
+    }
+}
+
+///What RomBuffer::info found out about a rom. The variant and the sound come from the opcodes the
+///program can reach, see RomBuffer::detect_format, the opcode groups count every two bytes, sprite
+///data too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    ///length in bytes, after padding to a whole instruction
+    pub size: usize,
+    pub variant: Chip8Variant,
+    ///whether it has an fx18, roms without one never beep
+    pub uses_sound_timer: bool,
+    ///how many opcodes start with each hexadecimal digit, indexed by that digit
+    pub opcode_groups: [usize; 16],
+}
+impl core::fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "{} bytes, looks like {:?}", self.size, self.variant)?;
+        if self.uses_sound_timer {
+            writeln!(f, "uses sound")?;
+        } else {
+            writeln!(f, "never makes a sound")?;
+        }
+        for (group, count) in self.opcode_groups.iter().enumerate() {
+            if *count > 0 {
+                writeln!(f, "{:X}xxx: {}", group, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///A chip8 program, checked to fit in memory
+#[derive(Clone)]
+pub struct RomBuffer {
+    buffer: Vec<u8>,
+}
+impl RomBuffer {
+    ///Reads a rom from a file, `.8o` files are octo source and get assembled first
+    #[cfg(feature = "std")]
+    pub fn new(file: impl AsRef<std::path::Path>) -> Result<Self, RomError> {
+        let file = file.as_ref();
+        let is_octo = file
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("8o"));
+        if is_octo {
+            return RomBuffer::from_octo(&std::fs::read_to_string(file)?);
+        }
+        RomBuffer::from_bytes(std::fs::read(file)?)
+    }
+    ///Writes the bytes of the rom to a file, so an assembled `.8o` program can be shared as a
+    ///plain rom that any interpreter runs
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, &self.buffer)
+    }
+    ///Assembles octo source into a rom, see the assembler module for what's supported
+    pub fn from_octo(source: &str) -> Result<Self, RomError> {
+        RomBuffer::from_bytes(assembler::assemble(source).map_err(RomError::Octo)?)
+    }
+    ///Checks that the rom fits in memory. Instructions are two bytes, so a rom with an odd length is
+    ///padded with a zero byte, making its last byte the high byte of a final instruction.
+    ///Empty and single byte roms load too, ram past them is zeroed so they start out executing
+    ///0000, which is decoded as a Noop.
+    pub fn from_bytes(mut buffer: Vec<u8>) -> Result<Self, RomError> {
+        if buffer.len() > RAM_SIZE - ROM_START_ADDRESS {
+            return Err(RomError::TooLarge(buffer.len()));
+        }
+        if buffer.len() % 2 == 1 {
+            buffer.push(0);
+        }
+        Ok(RomBuffer { buffer })
+    }
+
+    ///Guesses which dialect the rom is written for by looking for opcodes that only exist in the
+    ///extended ones. Only opcodes the program can reach from ROM_START_ADDRESS count, sprite data
+    ///like the 00 FF in the ibm logo would look like super-chip otherwise. It's still a heuristic,
+    ///code only reached through bnnn isn't followed, and roms without any extended opcodes are
+    ///assumed to be plain chip8.
+    pub fn detect_format(&self) -> Chip8Variant {
+        let mut variant = Chip8Variant::Chip8;
+        for opcode in self.reachable_opcodes() {
+            match opcode_variant(opcode) {
+                Chip8Variant::XoChip => return Chip8Variant::XoChip,
+                Chip8Variant::SuperChip => variant = Chip8Variant::SuperChip,
+                Chip8Variant::Chip8 => {}
+            }
+        }
+        variant
+    }
+
+    ///Facts about the rom that help picking settings before running it, see RomInfo
+    pub fn info(&self) -> RomInfo {
+        let mut opcode_groups = [0; 16];
+        for opcode in self.opcodes() {
+            opcode_groups[(opcode >> 12) as usize] += 1;
+        }
+        RomInfo {
+            size: self.buffer.len(),
+            variant: self.detect_format(),
+            uses_sound_timer: self
+                .reachable_opcodes()
+                .iter()
+                .any(|opcode| opcode & 0xF0FF == 0xF018),
+            opcode_groups,
+        }
+    }
+
+    ///A listing of the rom as if it was loaded at start_address, one `address: opcode  mnemonic`
+    ///line per two bytes like the trace. Opcodes that aren't instructions, usually sprite data, are
+    ///written as a DB directive with their two bytes. Code and data are only told apart by
+    ///decoding, so data that happens to decode is listed as an instruction.
+    pub fn disassemble(&self, start_address: usize) -> String {
+        let mut listing = String::new();
+        for (index, opcode) in self.opcodes().enumerate() {
+            let address = start_address + index * 2;
+            let line = match Instruction::decode(opcode) {
+                Some(instruction) => format!("{:04X}: {:04X}  {}\n", address, opcode, instruction),
+                None => format!(
+                    "{:04X}: {:04X}  DB {:#04X}, {:#04X}\n",
+                    address,
+                    opcode,
+                    opcode >> 8,
+                    opcode & 0xFF
+                ),
+            };
+            listing.push_str(&line);
+        }
+        listing
+    }
+
+    ///Every two bytes of the rom read as an opcode, sprite data included
+    fn opcodes(&self) -> impl Iterator<Item = u16> + '_ {
+        self.buffer
+            .chunks_exact(2)
+            .map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    ///The opcode at an address of the rom loaded at ROM_START_ADDRESS, None outside of it
+    fn opcode_at(&self, address: usize) -> Option<u16> {
+        let offset = address.checked_sub(ROM_START_ADDRESS)?;
+        let bytes = self.buffer.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    ///The opcodes that can run, found by following the jumps, calls and skips from the first
+    ///instruction of the rom. Bnnn and returns end a path, so do opcodes that aren't
+    ///instructions, apart from the xo-chip ones that can't be run yet but aren't data either.
+    fn reachable_opcodes(&self) -> Vec<u16> {
+        //f000 is followed by a 16 bit address, which skips have to jump over too
+        let length = |opcode: u16| if opcode == 0xF000 { 4 } else { 2 };
+        let mut visited = BTreeSet::new();
+        let mut opcodes = Vec::new();
+        let mut pending = vec![ROM_START_ADDRESS];
+        while let Some(address) = pending.pop() {
+            let Some(opcode) = self.opcode_at(address) else {
+                continue;
+            };
+            if !visited.insert(address) {
+                continue;
+            }
+            opcodes.push(opcode);
+            let next = address + length(opcode);
+            match Instruction::decode(opcode) {
+                Some(Instruction::Jump { nnn }) => pending.push(nnn as usize),
+                Some(Instruction::CallSubroutineAtNNN { nnn }) => {
+                    pending.push(nnn as usize);
+                    pending.push(next);
+                }
+                Some(
+                    Instruction::ReturnFromSubroutine
+                    | Instruction::Exit
+                    | Instruction::JumpToAddressPlusV0 { .. },
+                ) => {}
+                Some(
+                    Instruction::SkipNextInstructionIfXIsKK { .. }
+                    | Instruction::SkipNextInstructionIfXIsNotKK { .. }
+                    | Instruction::SkipNextInstructionIfXIsY { .. }
+                    | Instruction::SkipNextInstructionIfXIsNotY { .. }
+                    | Instruction::SkipIfVxPressed { .. }
+                    | Instruction::SkipIfVxNotPressed { .. },
+                ) => {
+                    pending.push(next);
+                    if let Some(skipped) = self.opcode_at(next) {
+                        pending.push(next + length(skipped));
+                    }
+                }
+                Some(_) => pending.push(next),
+                None if opcode_variant(opcode) == Chip8Variant::XoChip => pending.push(next),
+                None => {}
+            }
+        }
+        opcodes
+    }
+}
+
+///The first dialect the opcode exists in
+fn opcode_variant(opcode: u16) -> Chip8Variant {
+    let xo_chip = matches!(opcode & 0xF00F, 0x5002 | 0x5003)
+        || matches!(opcode & 0xF0FF, 0xF001 | 0xF03A)
+        || matches!(opcode, 0xF000 | 0xF002)
+        || opcode & 0xFFF0 == 0x00D0;
+    let super_chip = matches!(opcode, 0x00FB..=0x00FF)
+        || opcode & 0xFFF0 == 0x00C0
+        || opcode & 0xF00F == 0xD000
+        || matches!(opcode & 0xF0FF, 0xF030 | 0xF075 | 0xF085);
+    if xo_chip {
+        Chip8Variant::XoChip
+    } else if super_chip {
+        Chip8Variant::SuperChip
+    } else {
+        Chip8Variant::Chip8
+    }
+}
+
+#[derive(Clone)]
+///# All 16 8 bit registers, and the 16 bit I register
+struct Registers {
+    register: [u8; NUM_REGISTERS],
+    vindex: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+impl Registers {
+    fn new() -> Self {
+        Registers {
+            register: [0u8; NUM_REGISTERS],
+            vindex: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        }
+    }
+    fn set_index_register(&mut self, value: u16) {
+        self.vindex = value;
+    }
+    fn get_index_register(&self) -> u16 {
+        self.vindex
+    }
+    fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+    fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+    fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+    fn decrement_sound_timer(&mut self) {
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+    fn decrement_delay_timer(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+    }
+
+    fn get_register(&self, register: u8) -> u8 {
+        self.register[register as usize]
+    }
+    fn set_register(&mut self, register: u8, value: u8) {
+        self.register[register as usize] = value;
+    }
+}
+
+///The return addresses of running subroutines. Execute checks the depth before every call and
+///return and hands the rom a CpuError instead, so the asserts below only catch bugs in the cpu.
+#[derive(Clone)]
+struct Stack {
+    values: [u16; STACK_SIZE],
+}
+impl Stack {
+    fn new() -> Self {
+        Stack {
+            values: [0; STACK_SIZE],
+        }
+    }
+
+    fn get(&self, index: usize) -> u16 {
+        assert!(
+            index < STACK_SIZE,
+            "read stack index {} past the {} levels of the stack without checking the depth",
+            index,
+            STACK_SIZE
+        );
+        self.values[index]
+    }
+
+    fn set(&mut self, index: usize, value: u16) {
+        assert!(
+            index < STACK_SIZE,
+            "wrote stack index {} past the {} levels of the stack without checking the depth",
+            index,
+            STACK_SIZE
+        );
+        self.values[index] = value;
+    }
+}
+
+///Behaviours that differ between chip8 implementations, roms written for one of them may
+///misbehave on the others. The names follow the columns of the well known quirks test rom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    ///8xy6 and 8xyE shift vx in place instead of shifting vy into vx
+    pub shift: bool,
+    ///fx55 and fx65 leave I pointing past the last register they touched
+    pub memory: bool,
+    ///Bnnn is treated as Bxnn and jumps to xnn + vx instead of nnn + v0
+    pub jump: bool,
+    ///fx1E sets vf when I goes past 0xFFF, like the amiga interpreter does
+    pub i_overflow: bool,
+    ///Dxyn waits for the vertical blank interrupt, so at most one sprite is drawn per frame
+    pub vblank: bool,
+    ///Dxyn wraps pixels going past the right or bottom edge around to the other side instead of
+    ///clipping them. The starting position always wraps.
+    pub wrap: bool,
+    ///8xy1, 8xy2 and 8xy3 reset vf to 0 after computing, a side effect on the cosmac vip
+    pub logic: bool,
+    ///fx0A only finishes once a key is pressed and released again, a key that was already held
+    ///when the wait began has to be let go first, like on the cosmac vip
+    pub key_release: bool,
+}
+impl Quirks {
+    ///the behaviour of the original cosmac vip interpreter
+    pub fn chip8() -> Self {
+        Quirks {
+            shift: false,
+            memory: true,
+            jump: false,
+            i_overflow: false,
+            vblank: true,
+            wrap: false,
+            logic: true,
+            key_release: true,
+        }
+    }
+    ///the behaviour of superchip as most modern roms expect it, same as Quirks::superchip_modern
+    pub fn superchip() -> Self {
+        Quirks::superchip_modern()
+    }
+    ///superchip as emulators like octo run it, without waiting for vblank
+    pub fn superchip_modern() -> Self {
+        Quirks {
+            shift: true,
+            memory: false,
+            jump: true,
+            i_overflow: false,
+            vblank: false,
+            wrap: false,
+            logic: false,
+            key_release: false,
+        }
+    }
+    ///the original superchip 1.1 interpreter on the hp48, which waits for vblank when drawing in
+    ///low resolution. Here the wait happens in high resolution too.
+    pub fn superchip_legacy() -> Self {
+        Quirks {
+            vblank: true,
+            ..Quirks::superchip_modern()
+        }
+    }
+    ///the behaviour of xo-chip, which went back to the original shifts, jumps and fx55/fx65 but
+    ///wraps sprites around the edges and leaves vf alone after 8xy1, 8xy2 and 8xy3
+    pub fn xochip() -> Self {
+        Quirks {
+            shift: false,
+            memory: true,
+            jump: false,
+            i_overflow: false,
+            vblank: false,
+            wrap: true,
+            logic: false,
+            key_release: true,
+        }
+    }
+    ///looks up a preset by the name used on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Quirks::chip8()),
+            "superchip" => Some(Quirks::superchip()),
+            "superchip-legacy" => Some(Quirks::superchip_legacy()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+
+    ///The preset that fits roms written for the given dialect
+    pub fn for_variant(variant: Chip8Variant) -> Self {
+        match variant {
+            Chip8Variant::Chip8 => Quirks::chip8(),
+            Chip8Variant::SuperChip => Quirks::superchip(),
+            Chip8Variant::XoChip => Quirks::xochip(),
+        }
+    }
+}
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::chip8()
+    }
+}
+impl Quirks {
+    ///Whether a single quirk is on
+    pub fn is_on(&self, quirk: Quirk) -> bool {
+        match quirk {
+            Quirk::Shift => self.shift,
+            Quirk::Memory => self.memory,
+            Quirk::Jump => self.jump,
+            Quirk::IOverflow => self.i_overflow,
+            Quirk::Vblank => self.vblank,
+            Quirk::Wrap => self.wrap,
+            Quirk::Logic => self.logic,
+            Quirk::KeyRelease => self.key_release,
+        }
+    }
+
+    ///Turns a single quirk on or off
+    pub fn set(&mut self, quirk: Quirk, on: bool) {
+        let field = match quirk {
+            Quirk::Shift => &mut self.shift,
+            Quirk::Memory => &mut self.memory,
+            Quirk::Jump => &mut self.jump,
+            Quirk::IOverflow => &mut self.i_overflow,
+            Quirk::Vblank => &mut self.vblank,
+            Quirk::Wrap => &mut self.wrap,
+            Quirk::Logic => &mut self.logic,
+            Quirk::KeyRelease => &mut self.key_release,
+        };
+        *field = on;
+    }
+}
+
+///One of the fields of Quirks, so front-ends can list and toggle them one at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quirk {
+    Shift,
+    Memory,
+    Jump,
+    IOverflow,
+    Vblank,
+    Wrap,
+    Logic,
+    KeyRelease,
+}
+impl Quirk {
+    ///Every quirk, in the order of the fields of Quirks
+    pub const ALL: [Quirk; 8] = [
+        Quirk::Shift,
+        Quirk::Memory,
+        Quirk::Jump,
+        Quirk::IOverflow,
+        Quirk::Vblank,
+        Quirk::Wrap,
+        Quirk::Logic,
+        Quirk::KeyRelease,
+    ];
+}
+impl core::fmt::Display for Quirk {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let name = match self {
+            Quirk::Shift => "shift",
+            Quirk::Memory => "memory",
+            Quirk::Jump => "jump",
+            Quirk::IOverflow => "i overflow",
+            Quirk::Vblank => "vblank",
+            Quirk::Wrap => "wrap",
+            Quirk::Logic => "logic",
+            Quirk::KeyRelease => "key release",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+///Callback receiving the address and instruction of every executed instruction
+pub type Observer = Box<dyn FnMut(u16, &Instruction)>;
+
+///Callback receiving the display once every frame, see Cpu::on_frame
+pub type FrameHook = Box<dyn FnMut(&Framebuffer)>;
+
+///Conditions for Cpu::run_until
+pub mod until {
+    use super::Cpu;
+
+    ///The instruction at the address is up next
+    pub fn address(address: u16) -> impl Fn(&Cpu) -> bool {
+        move |cpu| cpu.program_counter() == address
+    }
+
+    ///Register V0 through VF holds the value
+    pub fn register(register: u8, value: u8) -> impl Fn(&Cpu) -> bool {
+        move |cpu| cpu.get_register(register).is_ok_and(|held| held == value)
+    }
+
+    ///No pixel is lit, like right after 00E0
+    pub fn blank_screen(cpu: &Cpu) -> bool {
+        cpu.framebuffer().is_blank()
+    }
+}
+
+pub struct Cpu {
+    display: Framebuffer,
+    ///Program counter, used to keep track of what to fetch,decode and execute from ram, initialized at 0x200
+    program_counter: u16,
+    memory: Ram,
+    registers: Registers,
+    stack: Stack, //stack for keeping track of where to return to after subroutine, can go into 16 nested subroutines before stackoverflow
+    stackpointer: u8, //only contains indexes to locations in the stack, so 0 through STACK_SIZE
+    quirks: Quirks,
+    ///when set, every executed instruction is written here as `pc: opcode  mnemonic`
+    #[cfg(feature = "std")]
+    trace: Option<Box<dyn std::io::Write>>,
+    ///set once the program jumped to its own address, which is how most roms end, or ran 00FD
+    halted: bool,
+    ///set by Dxyn when the vblank quirk is on, no more instructions should run until the next frame
+    waiting_for_vblank: bool,
+    ///source of cxkk's random numbers, seeded so runs can be reproduced
+    rng: StdRng,
+    ///called with the address and instruction of every decoded instruction, see Cpu::set_observer
+    observer: Option<Observer>,
+    ///number of instructions executed since the rom was loaded
+    cycles: u64,
+    ///which keys of the keypad are held down, indexed by key value
+    keyboard: [bool; NUM_KEYS],
+    ///super-chip's rpl user flags, fx75 and fx85 save and restore registers here
+    rpl: [u8; 8],
+    ///how fast Cpu::step_for runs instructions
+    instructions_per_second: u32,
+    ///the part of an instruction step_for owes from earlier calls
+    instruction_remainder: f64,
+    ///time passed since the timers last ticked
+    timer_remainder: Duration,
+    ///how many times per second step_for ticks the timers
+    refresh_rate: u32,
+    ///instructions already decoded, by address, when the decode cache is on
+    decode_cache: Option<Vec<Option<Instruction>>>,
+    ///how often each kind of instruction ran while profiling is on, see Cpu::set_profiling
+    profile: Option<BTreeMap<InstructionKind, u64>>,
+    ///the state before each of the last instructions, newest last, see Cpu::set_history
+    history: VecDeque<SavedState>,
+    ///how many states history keeps, 0 turns it off
+    history_capacity: usize,
+    ///ram right after the rom was loaded, for Cpu::reset
+    loaded_memory: Ram,
+    ///where the rom was loaded and started running
+    start_address: u16,
+    ///how many bytes of ram the rom took up when it was loaded
+    rom_length: u16,
+    ///the rows of the display that changed, see Cpu::take_dirty_rows
+    dirty_rows: [bool; HIGH_RES_DISPLAY_HEIGHT],
+    ///the display at the last vblank and at the one before it, see Cpu::previous_framebuffer
+    latest_frame: Framebuffer,
+    previous_frame: Framebuffer,
+    ///xo-chip's 1 bit sound sample, played instead of the beep once f002 loaded one
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    ///xo-chip's playback rate of the sound pattern, see Cpu::pitch
+    pitch: u8,
+    ///how many planes the last dxyn hit a lit pixel in, see Cpu::last_draw_collisions
+    last_draw_collisions: u8,
+    ///set whenever a sprite hits a lit pixel, see Cpu::take_collision
+    collided: bool,
+    ///scripted key presses and releases as (cycle, key, down), soonest first, see
+    ///Cpu::queue_key_events
+    key_events: VecDeque<(u64, u8, bool)>,
+    ///called with the display at every vblank, see Cpu::on_frame
+    frame_hook: Option<FrameHook>,
+    ///addresses that stop the cpu when an instruction writes them, see Cpu::add_watchpoint
+    watchpoints: BTreeSet<u16>,
+    ///the first watched address the running instruction wrote
+    watched_write: Option<u16>,
+    ///the write that stopped the cpu, see Cpu::watchpoint_hit
+    watchpoint_hit: Option<WatchpointHit>,
+    ///the fx0A that is waiting for a key with the key_release quirk on
+    key_wait: Option<KeyWait>,
+    ///what happens to opcodes that aren't instructions, see Cpu::set_unknown_opcode_policy
+    unknown_opcode_policy: UnknownOpcodePolicy,
+    ///the opcode the cpu halted on, see Cpu::unknown_opcode
+    unknown_opcode: Option<UnknownOpcode>,
+}
+
+///How far an fx0A waiting for a key press and release got
+#[derive(Clone, Copy)]
+struct KeyWait {
+    ///keys that were held when the wait began and haven't been let go since, they don't count yet
+    stale: [bool; NUM_KEYS],
+    ///the key that was pressed, the wait is over once it's released
+    pressed: Option<u8>,
+}
+
+///Everything an instruction can change, so Cpu::step_back can undo it
+struct SavedState {
+    ///None when the instruction left the display alone, which most don't touch
+    display: Option<Box<Framebuffer>>,
+    ///the rows a sprite was drawn over, as they were before, dxyn keeps just these
+    display_rows: Vec<(usize, [u8; HIGH_RES_DISPLAY_WIDTH])>,
+    program_counter: u16,
+    ///None when the instruction wrote nothing, like the display
+    memory: Option<Box<Ram>>,
+    registers: Registers,
+    stack: Stack,
+    stackpointer: u8,
+    halted: bool,
+    waiting_for_vblank: bool,
+    rng: StdRng,
+    cycles: u64,
+    rpl: [u8; 8],
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    last_draw_collisions: u8,
+    key_wait: Option<KeyWait>,
+}
+
+impl Cpu {
+    ///The opcode at the program counter, which can be past the end of ram after a skip or bnnn
+    ///near the top of ram
+    fn fetch(&self, ram: &Ram) -> Result<u16, OutOfBounds> {
+        ram.get(self.program_counter)
+    }
+
+    ///Decodes the opcode at the address, from the decode cache if it's on. None when it isn't an
+    ///instruction.
+    fn decode_at(&mut self, address: u16, opcode: u16) -> Option<Instruction> {
+        let cached = self
+            .decode_cache
+            .as_ref()
+            .and_then(|cache| cache[address as usize]);
+        if cached.is_some() {
+            return cached;
+        }
+
+        let instruction = Instruction::decode(opcode)?;
+        if let Some(cache) = &mut self.decode_cache {
+            cache[address as usize] = Some(instruction);
+        }
+        Some(instruction)
+    }
+
+    ///Writes a byte of ram for an instruction, forgetting the decoded instructions it is part of so
+    ///self modifying programs keep working with the decode cache on, and noting writes to watched
+    ///addresses
+    fn write_memory(&mut self, address: usize, value: u8) -> Result<(), OutOfBounds> {
+        self.store_memory(address, value)?;
+        if self.watched_write.is_none() && self.watchpoints.contains(&(address as u16)) {
+            self.watched_write = Some(address as u16);
+        }
+        Ok(())
+    }
+
+    ///Writes a byte of ram without checking the watchpoints, see Cpu::write_memory
+    fn store_memory(&mut self, address: usize, value: u8) -> Result<(), OutOfBounds> {
+        //ram as it was before the first write since the last saved state, for Cpu::step_back
+        if let Some(state) = self.history.back_mut() {
+            if state.memory.is_none() {
+                state.memory = Some(Box::new(self.memory.clone()));
+            }
+        }
+        self.memory.try_set(address, value)?;
+        if let Some(cache) = &mut self.decode_cache {
+            cache[address] = None;
+            if address > 0 {
+                cache[address - 1] = None;
+            }
+        }
+        Ok(())
+    }
+
+    ///Execute the instruction, for details on the instruction, check the instruction enum
+    ///definition. Instructions reading or writing past the end of ram, or returning without a
+    ///subroutine to return from, return an error
+    fn execute(&mut self, instruction: Instruction) -> Result<(), CpuError> {
+        if instruction.changes_whole_display() {
+            self.save_display_for_history();
+        }
+        match instruction {
+            Instruction::Noop => {
+                //do nothing...
+            }
+            //00E0
+            Instruction::ClearScreen => {
+                self.display.clear();
+                self.mark_display_dirty();
+            }
+            //00EE
+            Instruction::ReturnFromSubroutine => {
+                //the program counter already moved past this return
+                if self.stackpointer == 0 {
+                    return Err(CpuError::StackUnderflow(self.program_counter - 2));
+                }
+                self.stackpointer -= 1;
+                self.program_counter = self.stack.get(self.stackpointer as usize);
+            }
+            //00CN, scrolls are in pixels of the current resolution like on xo-chip and modern
+            //superchip. Superchip 1.1 scrolled by half as many pixels in low resolution, which
+            //isn't emulated, so low resolution roms written for it scroll twice as far here.
+            Instruction::ScrollDown { n } => {
+                self.display.scroll_down(n as usize);
+                self.mark_display_dirty();
+            }
+            //00DN
+            Instruction::ScrollUp { n } => {
+                self.display.scroll_up(n as usize);
+                self.mark_display_dirty();
+            }
+            //00FB
+            Instruction::ScrollRight => {
+                self.display.scroll_right(4);
+                self.mark_display_dirty();
+            }
+            //00FC
+            Instruction::ScrollLeft => {
+                self.display.scroll_left(4);
+                self.mark_display_dirty();
+            }
+            //00FE
+            Instruction::LowResolution => {
+                self.display.set_high_resolution(false);
+                self.mark_display_dirty();
+            }
+            //00FF
+            Instruction::HighResolution => {
+                self.display.set_high_resolution(true);
+                self.mark_display_dirty();
+            }
+            //00FD
+            Instruction::Exit => {
+                //stay on this instruction so the program counter shows where the program ended
+                self.program_counter -= 2;
+                self.halted = true;
+            }
+            //1NNN
+            Instruction::Jump { nnn } => {
+                //the program counter already moved past this jump
+                if nnn == self.program_counter - 2 {
+                    self.halted = true;
+                }
+                self.program_counter = nnn;
+            }
+            //2NNN
+            Instruction::CallSubroutineAtNNN { nnn } => {
+                if self.stackpointer as usize >= STACK_SIZE {
+                    return Err(CpuError::StackOverflow(self.program_counter - 2));
+                }
+                self.stack
+                    .set(self.stackpointer as usize, self.program_counter);
+                self.stackpointer += 1;
+                self.program_counter = nnn;
+            }
+            //3XKK
+            Instruction::SkipNextInstructionIfXIsKK { x, kk } => {
+                let vx = self.registers.get_register(x);
+                if vx == kk {
+                    self.program_counter += 2;
+                }
+            }
+            //4XKK
+            Instruction::SkipNextInstructionIfXIsNotKK { x, kk } => {
+                let vx = self.registers.get_register(x);
+
+                if vx != kk {
+                    self.program_counter += 2;
+                }
+            }
+            //5XY0
+            Instruction::SkipNextInstructionIfXIsY { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+
+                if vx == vy {
+                    self.program_counter += 2;
+                }
+            }
+            //6XKK
+            Instruction::LoadRegisterX { x, kk } => {
+                self.registers.set_register(x, kk);
+            }
+            //7XKK
+            Instruction::AddToRegisterX { x, kk } => {
+                let vx = self.registers.get_register(x);
+
+                let (tmp, _overflow) = vx.overflowing_add(kk); // as u16 + kk as u16;
+                self.registers.set_register(x, tmp);
+            }
+            //8xy0
+            Instruction::LoadRegisterXIntoY { x, y } => {
+                let vy = self.registers.get_register(y);
+                self.registers.set_register(x, vy);
+            }
+            //8xy1
+            Instruction::LoadXOrYinX { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+                self.registers.set_register(x, vx | vy);
+                self.reset_flag_for_logic();
+            }
+            //8xy2
+            Instruction::LoadXAndYInX { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+
+                self.registers.set_register(x, vx & vy);
+                self.reset_flag_for_logic();
+            }
+            //8xy3
+            Instruction::LoadXXorYInX { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+                self.registers.set_register(x, vx ^ vy);
+                self.reset_flag_for_logic();
+            }
+            //8xy4
+            Instruction::AddYToX { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+
+                let (res, fv) = vy.overflowing_add(vx);
+                self.registers.set_register(x, res);
+                self.registers.set_register(0xf, if fv { 1 } else { 0 });
+            }
+
+            //8xy5
+            Instruction::SubYFromX { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+
+                let (res, fv) = vx.overflowing_sub(vy);
+                self.registers.set_register(x, res);
+                self.registers.set_register(0xf, if fv { 0 } else { 1 });
+            }
+
+            //8xy6
+            Instruction::ShiftXRight1 { x, y } => {
+                let vx = self
+                    .registers
+                    .get_register(if self.quirks.shift { x } else { y });
+                let vf = if vx & 1 == 1 { 1 } else { 0 };
+
+                self.registers.set_register(x, vx.overflowing_shr(1).0);
+                self.registers.set_register(0xF, vf);
+            }
+
+            //8xyE
+            Instruction::ShiftXLeft1 { x, y } => {
+                let vx = self
+                    .registers
+                    .get_register(if self.quirks.shift { x } else { y });
+                let fv = (vx as u16 >> 7) & 1;
+                let res = vx.wrapping_shl(1);
+
+                self.registers.set_register(x, res);
+                self.registers
+                    .set_register(0xf, if fv == 1 { 1 } else { 0 });
+            }
+            //8xy7
+            Instruction::SubXFromY { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+                let (res, fv) = vy.overflowing_sub(vx);
+                self.registers.set_register(x, res);
+                self.registers.set_register(0xf, if fv { 0 } else { 1 });
+            }
+
+            //9XY0
+            Instruction::SkipNextInstructionIfXIsNotY { x, y } => {
+                let vx = self.registers.get_register(x);
+                let vy = self.registers.get_register(y);
+                if vx != vy {
+                    self.program_counter += 2;
+                }
+            }
+            //ANNN
+            Instruction::SetIndexRegister { nnn } => {
+                self.registers.set_index_register(nnn);
+            }
+            //BNNN
+            Instruction::JumpToAddressPlusV0 { nnn } => {
+                //with the jump quirk the highest nibble of nnn doubles as the register
+                let register = if self.quirks.jump {
+                    (nnn >> 8) as u8
+                } else {
+                    0
+                };
+                let offset = self.registers.get_register(register) as u16;
+                //ends up past ram at worst, the next fetch reports that as out of bounds
+                self.program_counter = nnn.wrapping_add(offset);
+            }
+            //cxkk
+            Instruction::SetXToRandom { x, kk } => {
+                let random_number = self.rng.gen_range(0..=255);
+                self.registers.set_register(x, random_number & kk);
+            }
+            //DXYN
+            Instruction::Display { x, y, n } => {
+                //drawing at (start_x, start_y) on the display, wraps around if out of bounds
+                let (width, height) = self.display_dimensions();
+                let start_x = self.registers.get_register(x) as usize % width;
+                let start_y = self.registers.get_register(y) as usize % height;
+
+                let sprite_start = self.registers.get_index_register() as usize;
+                self.registers.set_register(0xF, 0);
+                self.last_draw_collisions = 0;
+                self.waiting_for_vblank = self.quirks.vblank;
+
+                //dxy0 draws a 16 by 16 sprite in high resolution, two bytes to a row. In low
+                //resolution it draws nothing, like on the cosmac vip.
+                let large = n == 0 && self.display.is_high_resolution();
+                let (sprite_width, sprite_height) = if large { (16, 16) } else { (8, n as usize) };
+                let bytes_per_row = sprite_width / 8;
+
+                //move over all rows of the sprite
+                for sprite_row in 0..sprite_height {
+                    let row_start = sprite_start + sprite_row * bytes_per_row;
+                    //the row's pixels from the highest bit down
+                    let mut sprite = 0u16;
+                    for byte in 0..bytes_per_row {
+                        sprite |=
+                            (self.memory.try_get_byte(row_start + byte)? as u16) << (8 - byte * 8);
+                    }
+                    let row = start_y + sprite_row;
+                    if self.quirks.wrap || row < height {
+                        self.save_row_for_history(row % height);
+                    }
+                    for sprite_column in 0..sprite_width {
+                        //screen coordinates of this pixel
+                        let mut px = start_x + sprite_column;
+                        let mut py = start_y + sprite_row;
+
+                        let sprite_pixel_set = sprite >> (15 - sprite_column) & 1 == 1;
+
+                        if self.quirks.wrap {
+                            px %= width;
+                            py %= height;
+                        } else if px >= width || py >= height {
+                            //clipped, so as to *not* draw out of bounds of the display
+                            continue;
+                        }
+
+                        self.dirty_rows[py] = true;
+                        let lit = self.display.is_on(px, py);
+                        if lit && sprite_pixel_set {
+                            self.registers.set_register(0xf, 1);
+                            //only one plane is drawn, so the count is at most 1
+                            self.last_draw_collisions = 1;
+                            self.collided = true;
+                        }
+                        self.display.set(px, py, 0, lit ^ sprite_pixel_set);
+                    }
+                }
+            }
+            //exa1
+            Instruction::SkipIfVxNotPressed { x } => {
+                let key = self.registers.get_register(x) & 0xf;
+                if !self.key_state(key) {
+                    self.program_counter += 2;
+                }
+            }
+            //ex9e
+            Instruction::SkipIfVxPressed { x } => {
+                let key = self.registers.get_register(x) & 0xf;
+                if self.key_state(key) {
+                    self.program_counter += 2;
+                }
+            }
+            //fx0a
+            Instruction::WaitForKeyPressed { x } => {
+                //block by re-executing this instruction until the wait is over
+                let key = if self.quirks.key_release {
+                    self.key_pressed_and_released()
+                } else {
+                    self.pressed_keys().next()
+                };
+                match key {
+                    Some(key) => self.registers.set_register(x, key),
+                    None => self.program_counter -= 2,
+                }
+            }
+            //fx07
+            Instruction::SetXToDelayTimer { x } => {
+                let vdt = self.registers.get_delay_timer();
+                self.registers.set_register(x, vdt);
+            }
+            //fx15
+            Instruction::SetDelayTimerToX { x } => {
+                let vx = self.registers.get_register(x);
+                self.registers.set_delay_timer(vx);
+            }
+            Instruction::SetSoundTimerToX { x } => {
+                let vx = self.registers.get_register(x);
+                self.registers.set_sound_timer(vx);
+            }
+            //fx1E
+            Instruction::AddXtoI { x } => {
+                let vx = self.registers.get_register(x) as u16;
+                let vi = self.registers.get_index_register();
+                let added = vi.wrapping_add(vx);
+
+                self.registers.set_index_register(added);
+                if self.quirks.i_overflow {
+                    self.registers
+                        .set_register(0xf, if added > 0xfff { 1 } else { 0 });
+                }
+            }
+            //fx29
+            Instruction::SetIToSpriteX { x } => {
+                let vx = (self.registers.get_register(x) & 0xf) as u16 * 5;
+                //the sprite at *index* x, not location x.
+                self.registers.set_index_register(vx);
+            }
+            //fx30
+            Instruction::SetIToLargeSpriteX { x } => {
+                let vx = (self.registers.get_register(x) & 0xf) as u16 * 10;
+                self.registers
+                    .set_index_register(LARGE_FONT_ADDRESS as u16 + vx);
+            }
+            //f002
+            Instruction::LoadAudioPattern => {
+                let start = self.registers.get_index_register() as usize;
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.memory.try_get_byte(start + offset)?;
+                }
+                self.audio_pattern = Some(pattern);
+            }
+            //fx3a
+            Instruction::SetPitchToX { x } => {
+                self.pitch = self.registers.get_register(x);
+            }
+            Instruction::LoadBCDOfX { x } => {
+                let vx = self.registers.get_register(x);
+                let store_index = self.registers.get_index_register() as usize;
+                //I is at most 0xFFFF, so these sums fit and an I near the end of ram errors out
+                self.write_memory(store_index, vx / 100)?;
+                self.write_memory(store_index + 1, (vx % 100) / 10)?;
+                self.write_memory(store_index + 2, (vx % 100) % 10)?;
+            }
+            //fx55
+            Instruction::Write0ThroughX { x } => {
+                let vi = self.registers.get_index_register() as usize;
+
+                for register in 0..x + 1 {
+                    let register_value = self.registers.get_register(register);
+                    self.write_memory(vi + register as usize, register_value)?;
+                }
+                if self.quirks.memory {
+                    self.registers
+                        .set_index_register((vi as u16).wrapping_add(x as u16 + 1));
+                }
+            }
+            //fx65
+            Instruction::Load0ThroughX { x } => {
+                let vi = self.registers.get_index_register() as usize;
+                for i in 0..x + 1 {
+                    let value = self.memory.try_get_byte(vi + i as usize)?;
+                    self.registers.set_register(i, value);
+                }
+                if self.quirks.memory {
+                    self.registers
+                        .set_index_register((vi as u16).wrapping_add(x as u16 + 1));
+                }
+            }
+            //fx75
+            Instruction::WriteFlags0ThroughX { x } => {
+                //there are only 8 flags, higher registers are left out
+                for register in 0..=x.min(7) {
+                    self.rpl[register as usize] = self.registers.get_register(register);
+                }
+            }
+            //fx85
+            Instruction::LoadFlags0ThroughX { x } => {
+                for register in 0..=x.min(7) {
+                    self.registers
+                        .set_register(register, self.rpl[register as usize]);
+                }
+            }
+        }
+        Ok(())
+    }
+    ///Moves the fx0A wait of the key_release quirk along, returns the key once one was pressed and
+    ///released after the wait began
+    fn key_pressed_and_released(&mut self) -> Option<u8> {
+        let keyboard = self.keyboard;
+        let wait = self.key_wait.get_or_insert(KeyWait {
+            stale: keyboard,
+            pressed: None,
+        });
+        for (stale, &down) in wait.stale.iter_mut().zip(keyboard.iter()) {
+            *stale &= down;
+        }
+        match wait.pressed {
+            Some(key) if !keyboard[key as usize] => {
+                self.key_wait = None;
+                Some(key)
+            }
+            Some(_) => None,
+            None => {
+                wait.pressed = (0..NUM_KEYS as u8)
+                    .find(|&key| keyboard[key as usize] && !wait.stale[key as usize]);
+                None
+            }
+        }
+    }
+
+    ///With the logic quirk on, 8xy1, 8xy2 and 8xy3 leave vf at 0
+    fn reset_flag_for_logic(&mut self) {
+        if self.quirks.logic {
+            self.registers.set_register(0xf, 0);
+        }
+    }
+    ///Fetches, decodes and executes a single instruction, the timers only count down in vblank
+    pub fn cycle(&mut self) -> Result<(), CpuError> {
+        while let Some(&(cycle, key, down)) = self.key_events.front() {
+            if cycle > self.cycles {
+                break;
+            }
+            self.set_key_state(key, down);
+            self.key_events.pop_front();
+        }
+        //a program that ran off the end of ram stops here, before anything changes
+        let opcode = self.fetch(&self.memory)?;
+        self.watchpoint_hit = None;
+        self.unknown_opcode = None;
+        self.save_state();
+        let address = self.program_counter;
+
+        self.program_counter += 2;
+        self.cycles += 1;
+
+        let instruction = match self.decode_at(address, opcode) {
+            Some(instruction) => instruction,
+            None => match self.unknown_opcode_policy {
+                UnknownOpcodePolicy::Halt => {
+                    //stays on the opcode, like 00FD, so the program counter shows where it is
+                    self.program_counter = address;
+                    self.halted = true;
+                    self.unknown_opcode = Some(UnknownOpcode { address, opcode });
+                    return Ok(());
+                }
+                UnknownOpcodePolicy::Skip => return Ok(()),
+                UnknownOpcodePolicy::Noop => Instruction::Noop,
+            },
+        };
+
+        if let Some(observer) = &mut self.observer {
+            observer(address, &instruction);
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(trace) = &mut self.trace {
+            if let Err(error) = writeln!(trace, "{:04X}: {:04X}  {}", address, opcode, instruction)
+            {
+                eprintln!("could not write trace, tracing is turned off: {}", error);
+                self.trace = None;
+            }
+        }
+
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(instruction.kind()).or_insert(0) += 1;
+        }
+
+        let result = self.execute(instruction);
+        if let Some(written) = self.watched_write.take() {
+            self.watchpoint_hit = Some(WatchpointHit {
+                address: written,
+                program_counter: address,
+                instruction,
+            });
+        }
+        result
+    }
+
+    ///Runs instructions until the condition holds, the program halts or max_cycles instructions
+    ///ran, whichever comes first. Returns how many ran. The condition is checked before every
+    ///instruction, so nothing runs when it already holds. See the until module for common ones.
+    pub fn run_until(
+        &mut self,
+        condition: impl Fn(&Cpu) -> bool,
+        max_cycles: usize,
+    ) -> Result<usize, CpuError> {
+        let mut cycles = 0;
+        while cycles < max_cycles && !self.halted && !condition(self) {
+            self.cycle()?;
+            cycles += 1;
+            if self.watchpoint_hit.is_some() {
+                break;
+            }
+        }
+        Ok(cycles)
+    }
+
+    ///Runs the next instruction, or the whole subroutine when it's a 2nnn call: that runs until the
+    ///call returns to the instruction after it, at most max_cycles instructions. The stack depth is
+    ///checked too, so a recursive call passing the same address doesn't stop it early. Returns how
+    ///many instructions ran.
+    pub fn step_over(&mut self, max_cycles: usize) -> Result<usize, CpuError> {
+        if !matches!(
+            self.peek_instruction(),
+            Some(Instruction::CallSubroutineAtNNN { .. })
+        ) {
+            self.cycle()?;
+            return Ok(1);
+        }
+        let return_address = self.program_counter + 2;
+        let depth = self.stackpointer;
+        self.cycle()?;
+        let cycles = self.run_until(
+            |cpu| cpu.program_counter == return_address && cpu.stackpointer <= depth,
+            max_cycles.saturating_sub(1),
+        )?;
+        Ok(cycles + 1)
+    }
+
+    ///Runs one frame for front-ends that count instructions instead of time: up to budget
+    ///instructions followed by a vblank. It stops early when running more this frame can't change
+    ///anything, or when a watchpoint is hit. A watchpoint stops it before the vblank, so the
+    ///frame can be picked up again, the other outcomes end the frame.
+    pub fn run_frame(&mut self, budget: usize) -> Result<FrameOutcome, CpuError> {
+        let mut outcome = FrameOutcome::Completed;
+        for _ in 0..budget {
+            if self.halted {
+                outcome = FrameOutcome::Halted;
+                break;
+            }
+            if self.waiting_for_vblank {
+                outcome = FrameOutcome::WaitingForVblank;
+                break;
+            }
+            let address = self.program_counter;
+            self.cycle()?;
+            if self.watchpoint_hit.is_some() {
+                return Ok(FrameOutcome::Watchpoint);
+            }
+            //fx0A runs again until a key comes, and the keys don't change in the middle of a frame
+            if self.program_counter == address
+                && matches!(
+                    self.peek_instruction(),
+                    Some(Instruction::WaitForKeyPressed { .. })
+                )
+            {
+                outcome = FrameOutcome::WaitingForKey;
+                break;
+            }
+        }
+        self.vblank();
+        Ok(outcome)
+    }
+
+    ///Runs as many instructions as fit in the elapsed time at the cpu's speed, ticking the timers
+    ///for every frame that passed at the refresh rate. Time left over is carried into the next
+    ///call.
+    pub fn step_for(&mut self, elapsed: Duration) -> Result<(), CpuError> {
+        let mut elapsed = elapsed.min(MAX_STEP);
+        let frame = self.frame_duration();
+
+        //runs up to every frame boundary separately, so the vblank quirk lets each frame draw
+        while !elapsed.is_zero() && self.watchpoint_hit.is_none() {
+            let step = elapsed.min(frame - self.timer_remainder);
+            elapsed -= step;
+            self.run_instructions_for(step)?;
+
+            self.timer_remainder += step;
+            if self.timer_remainder >= frame {
+                self.timer_remainder -= frame;
+                self.vblank();
+            }
+        }
+        Ok(())
+    }
+
+    ///How many times per second Cpu::step_for ticks the timers
+    pub fn refresh_rate(&self) -> u32 {
+        self.refresh_rate
+    }
+
+    ///How long a frame lasts at the refresh rate
+    pub fn frame_duration(&self) -> Duration {
+        frame_duration(self.refresh_rate)
+    }
+
+    ///The quirks the cpu runs with
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    ///Changes the quirks while the rom runs, they take effect from the next instruction on
+    ///without a reset. A sprite waiting for vblank or an fx0A waiting for a key release stops
+    ///waiting when its quirk is turned off.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        if !quirks.vblank {
+            self.waiting_for_vblank = false;
+        }
+        if !quirks.key_release {
+            self.key_wait = None;
+        }
+        self.quirks = quirks;
+    }
+
+    ///Changes how many times per second Cpu::step_for ticks the timers, like 50 for roms made for
+    ///pal machines. A rate of 0 is treated as 1.
+    pub fn set_refresh_rate(&mut self, refresh_rate: u32) {
+        self.refresh_rate = refresh_rate.max(1);
+        //the frame in progress may be longer than a frame at the new rate
+        self.timer_remainder = Duration::ZERO;
+    }
+
+    fn run_instructions_for(&mut self, elapsed: Duration) -> Result<(), CpuError> {
+        self.instruction_remainder += elapsed.as_secs_f64() * self.instructions_per_second as f64;
+        while self.instruction_remainder >= 1.0 {
+            if self.halted || self.waiting_for_vblank {
+                //a stopped cpu doesn't build up instructions to run later
+                self.instruction_remainder %= 1.0;
+                break;
+            }
+            self.instruction_remainder -= 1.0;
+            self.cycle()?;
+            if self.watchpoint_hit.is_some() {
+                self.instruction_remainder %= 1.0;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn save_state(&mut self) {
+        if self.history_capacity == 0 {
+            return;
+        }
+        if self.history.len() == self.history_capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(SavedState {
+            display: None,
+            display_rows: Vec::new(),
+            program_counter: self.program_counter,
+            memory: None,
+            registers: self.registers.clone(),
+            stack: self.stack.clone(),
+            stackpointer: self.stackpointer,
+            halted: self.halted,
+            waiting_for_vblank: self.waiting_for_vblank,
+            rng: self.rng.clone(),
+            cycles: self.cycles,
+            rpl: self.rpl,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            last_draw_collisions: self.last_draw_collisions,
+            key_wait: self.key_wait,
+        });
+    }
+
+    ///Keeps the display as it was before the running instruction changed it, so Cpu::step_back
+    ///can put it back. Only those instructions keep a copy, the display is 8 KB.
+    fn save_display_for_history(&mut self) {
+        if let Some(state) = self.history.back_mut() {
+            if state.display.is_none() {
+                state.display = Some(Box::new(self.display.clone()));
+            }
+        }
+    }
+
+    ///Keeps a row of the display as it was before a sprite is drawn over it, see
+    ///Cpu::save_display_for_history. A sprite covers a few rows, so this is a lot less than the
+    ///whole display.
+    fn save_row_for_history(&mut self, y: usize) {
+        if let Some(state) = self.history.back_mut() {
+            let saved = state.display.is_some() || state.display_rows.iter().any(|row| row.0 == y);
+            if !saved {
+                state.display_rows.push((y, self.display.pixels[y]));
+            }
+        }
+    }
+
+    ///Undoes the last instruction, returns false and changes nothing when there is no history left.
+    ///The timers go back to what they were before it, the keypad is left as it is.
+    pub fn step_back(&mut self) -> bool {
+        let Some(state) = self.history.pop_back() else {
+            return false;
+        };
+        if let Some(display) = state.display {
+            self.display = *display;
+            self.mark_display_dirty();
+        }
+        for (y, row) in state.display_rows {
+            self.display.pixels[y] = row;
+            self.dirty_rows[y] = true;
+        }
+        self.program_counter = state.program_counter;
+        if let Some(memory) = state.memory {
+            self.memory = *memory;
+        }
+        self.registers = state.registers;
+        self.stack = state.stack;
+        self.stackpointer = state.stackpointer;
+        self.halted = state.halted;
+        self.waiting_for_vblank = state.waiting_for_vblank;
+        self.rng = state.rng;
+        self.cycles = state.cycles;
+        self.rpl = state.rpl;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.last_draw_collisions = state.last_draw_collisions;
+        self.key_wait = state.key_wait;
+        self.unknown_opcode = None;
+        //ram may have changed under the cached instructions
+        if self.decode_cache.is_some() {
+            self.set_decode_cache(true);
+        }
+        true
+    }
+
+    ///Restarts the loaded rom as if it was just loaded. Ram, registers, the stack, timers and the
+    ///display are reset, settings like the quirks, speed, seed and trace are kept.
+    pub fn reset(&mut self) {
+        self.display = Framebuffer::new();
+        self.latest_frame = self.display.clone();
+        self.previous_frame = self.display.clone();
+        self.mark_display_dirty();
+        self.program_counter = self.start_address;
+        self.memory = self.loaded_memory.clone();
+        self.registers = Registers::new();
+        self.stack = Stack::new();
+        self.stackpointer = 0;
+        self.halted = false;
+        self.waiting_for_vblank = false;
+        self.cycles = 0;
+        self.rpl = [0; 8];
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.last_draw_collisions = 0;
+        self.collided = false;
+        self.key_events.clear();
+        self.watched_write = None;
+        self.watchpoint_hit = None;
+        self.unknown_opcode = None;
+        self.key_wait = None;
+        self.instruction_remainder = 0.0;
+        self.timer_remainder = Duration::ZERO;
+        self.history.clear();
+        if self.decode_cache.is_some() {
+            self.set_decode_cache(true);
+        }
+    }
+
+    ///Whether Cpu::step_back has anything to undo
+    pub fn can_step_back(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    ///Keeps the state before each of the last `capacity` instructions so Cpu::step_back can undo
+    ///them, the oldest are forgotten first. States only copy ram and the display when the
+    ///instruction changed them, and a sprite only keeps the rows it drew on, so most are a few
+    ///hundred bytes. 0 (the default) turns it off and clears what was kept.
+    pub fn set_history(&mut self, capacity: usize) {
+        self.history_capacity = capacity;
+        while self.history.len() > capacity {
+            self.history.pop_front();
+        }
+    }
+
+    ///Creates a cpu with the rom loaded and everything else at its default, see CpuBuilder
+    pub fn new(rom: RomBuffer) -> Self {
+        CpuBuilder::new(rom).build()
+    }
+
+    ///Creates a cpu like Cpu::new, but with a fixed seed so cxkk produces the same numbers every run
+    pub fn with_seed(rom: RomBuffer, seed: u64) -> Self {
+        CpuBuilder::new(rom).seed(seed).build()
+    }
+
+    ///The display as it currently is
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    ///The width and height of the display in pixels, which change when the program switches
+    ///between low and high resolution. Front-ends should check it every frame.
+    pub fn display_dimensions(&self) -> (usize, usize) {
+        (self.display.width(), self.display.height())
+    }
+
+    ///The display as it was at the vblank before the last one, so a front-end can show what was
+    ///lit in either of the last two frames. Sprites moved by erasing and redrawing them with xor
+    ///are often missing from one of those frames, which is what makes them flicker.
+    pub fn previous_framebuffer(&self) -> &Framebuffer {
+        &self.previous_frame
+    }
+
+    ///Whether the display may have changed since the last call, so front-ends can skip redrawing
+    ///an unchanged screen. A new cpu starts out dirty. This takes the dirty rows too, see
+    ///Cpu::take_dirty_rows.
+    pub fn take_display_dirty(&mut self) -> bool {
+        self.take_dirty_rows().next().is_some()
+    }
+
+    ///The rows of the display that may have changed since the last call, from top to bottom, so
+    ///front-ends can redraw just those. A new cpu starts out with every row dirty.
+    pub fn take_dirty_rows(&mut self) -> impl Iterator<Item = usize> {
+        let rows = core::mem::replace(&mut self.dirty_rows, [false; HIGH_RES_DISPLAY_HEIGHT]);
+        (0..self.display.height()).filter(move |&row| rows[row])
+    }
+
+    ///Marks every row of the display as changed
+    fn mark_display_dirty(&mut self) {
+        self.dirty_rows = [true; HIGH_RES_DISPLAY_HEIGHT];
+    }
+
+    ///Signals the start of a new 60Hz frame, the timers count down and the wait of a sprite drawn
+    ///with the vblank quirk ends
+    pub fn vblank(&mut self) {
+        if let Some(hook) = &mut self.frame_hook {
+            hook(&self.display);
+        }
+        self.previous_frame = core::mem::replace(&mut self.latest_frame, self.display.clone());
+        self.registers.decrement_sound_timer();
+        self.registers.decrement_delay_timer();
+        self.waiting_for_vblank = false;
+    }
+
+    ///Whether the rest of this frame's cycles should be skipped, see Quirks::vblank
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    ///Whether the sound timer is running, the front-end should beep while it is
+    pub fn is_beeping(&self) -> bool {
+        self.registers.sound_timer > 0
+    }
+
+    ///The xo-chip sound pattern, 128 bits played from the highest bit of the first byte on while
+    ///the sound timer runs. None until the program loads one with f002, until then it beeps.
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    ///The xo-chip pitch register, the pattern plays at 4000 * 2^((pitch - 64) / 48) bits per
+    ///second, so the default of 64 plays it at 4000
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    ///How many planes the last sprite drawn with dxyn hit a lit pixel in, the collision VF was
+    ///set for without having to read VF. Only a single plane is emulated, so it's 0 or 1.
+    pub fn last_draw_collisions(&self) -> u8 {
+        self.last_draw_collisions
+    }
+
+    ///Whether any sprite hit a lit pixel since the last call. Front-ends that only look once a
+    ///frame can't tell from Cpu::last_draw_collisions, since more sprites may have been drawn since.
+    pub fn take_collision(&mut self) -> bool {
+        core::mem::take(&mut self.collided)
+    }
+
+    ///Whether the program ended by jumping to itself or exiting, cycling any further changes nothing
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    ///Decides what happens when the program runs into an opcode that isn't an instruction, takes
+    ///effect from the next cycle on
+    pub fn set_unknown_opcode_policy(&mut self, policy: UnknownOpcodePolicy) {
+        self.unknown_opcode_policy = policy;
+    }
+
+    ///The opcode that halted the cpu with UnknownOpcodePolicy::Halt. It is forgotten when the
+    ///next instruction runs, the program counter moves or the cpu resets.
+    pub fn unknown_opcode(&self) -> Option<&UnknownOpcode> {
+        self.unknown_opcode.as_ref()
+    }
+
+    ///Starts writing every executed instruction to the output, one line per instruction
+    #[cfg(feature = "std")]
+    pub fn enable_trace(&mut self, output: impl std::io::Write + 'static) {
+        self.trace = Some(Box::new(output));
+    }
+
+    ///How many instructions have been executed since the rom was loaded
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    ///The address of the instruction that runs next, for debuggers
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    ///The I register, for debuggers
+    pub fn index_register(&self) -> u16 {
+        self.registers.get_index_register()
+    }
+
+    ///The value of the delay timer, for debuggers
+    pub fn delay_timer(&self) -> u8 {
+        self.registers.get_delay_timer()
+    }
+
+    ///The value of the sound timer, for debuggers
+    pub fn sound_timer(&self) -> u8 {
+        self.registers.sound_timer
+    }
+
+    ///How many subroutines are being run, the next return address goes at this index of the stack
+    pub fn stack_pointer(&self) -> u8 {
+        self.stackpointer
+    }
+
+    ///Moves the program counter, so a debugger can jump to a routine to try it out. Addresses
+    ///that leave no room for a whole opcode before the end of ram are refused. Odd addresses are
+    ///allowed, but instructions normally sit at even ones, so front-ends may want to warn. A
+    ///halted program carries on from the new address, and Cpu::step_back can undo the move.
+    pub fn set_program_counter(&mut self, address: u16) -> Result<(), OutOfBounds> {
+        if address as usize + 1 >= RAM_SIZE {
+            return Err(OutOfBounds(address as usize));
+        }
+        self.save_state();
+        self.program_counter = address;
+        self.halted = false;
+        self.unknown_opcode = None;
+        self.key_wait = None;
+        Ok(())
+    }
+
+    ///The instruction at the program counter, which runs next, without running it. None when the
+    ///opcode there isn't an instruction, so debuggers can show it as data instead, or when the
+    ///program counter ran off the end of ram.
+    pub fn peek_instruction(&self) -> Option<Instruction> {
+        Instruction::decode(self.fetch(&self.memory).ok()?)
+    }
+
+    ///How many instructions Cpu::step_for runs per second
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    ///Tells the cpu whether a key of the keypad is held down, keys past 0xF are ignored
+    pub fn set_key_state(&mut self, key: u8, down: bool) {
+        if let Some(state) = self.keyboard.get_mut(key as usize) {
+            *state = down;
+        }
+    }
+
+    ///Whether a key of the keypad is held down, keys past 0xF never are
+    pub fn key_state(&self, key: u8) -> bool {
+        self.keyboard.get(key as usize).copied().unwrap_or(false)
+    }
+
+    ///The keys that are held down, from lowest to highest
+    pub fn pressed_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..NUM_KEYS as u8).filter(move |&key| self.key_state(key))
+    }
+
+    ///Scripts the keypad for replaying a recorded run, each event is (cycle, key, down). An event
+    ///is applied right before the instruction that runs once Cpu::cycle_count reached its cycle,
+    ///so together with a fixed seed a run plays out the same every time. Events join the ones
+    ///already queued, events for cycles that already passed apply before the next instruction.
+    ///Cpu::reset drops the queue.
+    pub fn queue_key_events(&mut self, events: Vec<(u64, u8, bool)>) {
+        self.key_events.extend(events);
+        //the sort is stable, so events for the same cycle keep their order
+        self.key_events
+            .make_contiguous()
+            .sort_by_key(|&(cycle, _, _)| cycle);
+    }
+
+    ///Lets go of every key, for front-ends that only hear about key presses and releases and can
+    ///miss a release, like when the window loses focus
+    pub fn release_all_keys(&mut self) {
+        self.keyboard = [false; NUM_KEYS];
+    }
+
+    ///A copy of all of ram, for comparing against other emulators
+    pub fn memory_dump(&self) -> [u8; RAM_SIZE] {
+        self.memory.bytes
+    }
+
+    ///Reads a byte of ram, for debuggers
+    pub fn get_memory(&self, address: u16) -> Result<u8, OutOfBounds> {
+        self.memory.try_get_byte(address as usize)
+    }
+
+    ///Overwrites a byte of ram, for debuggers
+    pub fn set_memory(&mut self, address: u16, value: u8) -> Result<(), OutOfBounds> {
+        self.store_memory(address as usize, value)
+    }
+
+    ///Stops the cpu right after an instruction writes the address, to catch self modifying code
+    ///or find where a score is kept. Cpu::step_for and Cpu::run_until run no further instructions
+    ///in that call, and Cpu::watchpoint_hit tells which instruction it was. Writes through
+    ///Cpu::set_memory don't count.
+    pub fn add_watchpoint(&mut self, address: u16) {
+        self.watchpoints.insert(address);
+    }
+
+    ///Stops watching the address
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.watchpoints.remove(&address);
+    }
+
+    ///The watched write made by the last instruction that ran, if it made one
+    pub fn watchpoint_hit(&self) -> Option<&WatchpointHit> {
+        self.watchpoint_hit.as_ref()
+    }
+
+    ///Turns the decode cache on or off. With it on every address is only decoded the first time
+    ///it runs, which saves time in loops. It is off by default, so decoding is always live.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = if enabled {
+            Some(vec![None; RAM_SIZE])
+        } else {
+            None
+        };
+    }
+
+    ///Turns the profiler on or off. While it's on every instruction that runs is tallied by its
+    ///kind, see Cpu::profile_report. It is off by default and costs nothing then, turning it
+    ///on or off starts the tally over.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled { Some(BTreeMap::new()) } else { None };
+    }
+
+    ///How many times each kind of instruction ran since profiling was turned on, most frequent
+    ///first. Kinds that never ran are left out, and it's empty while profiling is off.
+    pub fn profile_report(&self) -> Vec<(InstructionKind, u64)> {
+        let mut report: Vec<_> = self
+            .profile
+            .iter()
+            .flatten()
+            .map(|(&kind, &count)| (kind, count))
+            .collect();
+        report.sort_by_key(|&(_, count)| core::cmp::Reverse(count));
+        report
+    }
+
+    ///Reads register V0 through VF, for debuggers
+    pub fn get_register(&self, register: u8) -> Result<u8, InvalidRegister> {
+        if register as usize >= NUM_REGISTERS {
+            return Err(InvalidRegister(register));
+        }
+        Ok(self.registers.get_register(register))
+    }
+
+    ///Overwrites register V0 through VF, for debuggers
+    pub fn set_register(&mut self, register: u8, value: u8) -> Result<(), InvalidRegister> {
+        if register as usize >= NUM_REGISTERS {
+            return Err(InvalidRegister(register));
+        }
+        self.registers.set_register(register, value);
+        Ok(())
+    }
+
+    ///The return addresses of the subroutines being run, outermost first, for debuggers
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.stack.values[..self.stackpointer as usize].to_vec()
+    }
+
+    ///How ram is laid out: the fonts, the rom as it was loaded and the unused space around them.
+    ///Every region runs from its first address up to, but not including, the second one, and
+    ///together they cover all of ram in order.
+    pub fn memory_regions(&self) -> Vec<(u16, u16, RegionKind)> {
+        let large_font_end = (LARGE_FONT_ADDRESS + LARGE_FONT.len()) as u16;
+        let rom_end = self.start_address + self.rom_length;
+        let regions = [
+            (0, FONT_SIZE as u16, RegionKind::Font),
+            (FONT_SIZE as u16, large_font_end, RegionKind::LargeFont),
+            (large_font_end, self.start_address, RegionKind::Unused),
+            (self.start_address, rom_end, RegionKind::Program),
+            (rom_end, RAM_SIZE as u16, RegionKind::Unused),
+        ];
+        regions
+            .iter()
+            .copied()
+            .filter(|(start, end, _)| start < end)
+            .collect()
+    }
+
+    ///Whether two cpus are in the same state: the same registers, I, timers, ram, call stack,
+    ///program counter, rpl flags and display. Settings like the quirks and speed, the random
+    ///number generator and hooks like the trace aren't compared, so this tells whether two runs
+    ///fed the same input ended up in the same place. See Cpu::snapshot for finding where they
+    ///differ.
+    pub fn equivalent(&self, other: &Cpu) -> bool {
+        self.program_counter == other.program_counter
+            && self.registers.register == other.registers.register
+            && self.registers.vindex == other.registers.vindex
+            && self.registers.delay_timer == other.registers.delay_timer
+            && self.registers.sound_timer == other.registers.sound_timer
+            && self.memory.bytes == other.memory.bytes
+            && self.call_stack() == other.call_stack()
+            && self.rpl == other.rpl
+            && self.display == other.display
+    }
+
+    ///The state of the cpu right now, for comparing it against another run
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            cycles: self.cycles,
+            program_counter: self.program_counter,
+            index: self.registers.get_index_register(),
+            registers: self.registers.register,
+            delay_timer: self.registers.delay_timer,
+            sound_timer: self.registers.sound_timer,
+            call_stack: self.call_stack(),
+            memory_hash: fnv1a(self.memory.bytes.iter().copied()),
+            framebuffer_hash: fnv1a(self.display.pixels()),
+        }
+    }
+
+    ///Calls the hook with the display once per frame, replacing any earlier hook, so frames can be
+    ///sent to a video encoder, a stream or a test without the emulator knowing about any of them.
+    ///A frame is a call to Cpu::vblank, the hook sees the display after all of the frame's
+    ///instructions ran and before the timers count down. With Cpu::step_for that is refresh_rate
+    ///times per second of emulated time, nothing is sent while no time passes, like when paused.
+    pub fn on_frame(&mut self, hook: impl FnMut(&Framebuffer) + 'static) {
+        self.frame_hook = Some(Box::new(hook));
+    }
+
+    ///Calls the observer with the address and instruction of every instruction right before it
+    ///executes, replacing any earlier observer. The observer owns whatever state it keeps.
+    pub fn set_observer(&mut self, observer: impl FnMut(u16, &Instruction) + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    ///Stops tracing and hands back the output the trace was written to
+    #[cfg(feature = "std")]
+    pub fn take_trace(&mut self) -> Option<Box<dyn std::io::Write>> {
+        self.trace.take()
+    }
+}
+
+///A seed that differs between runs
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn clock_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+///Without std, and on the web which has no clock without javascript, there is nothing to seed
+///from, so front-ends there should pass their own seed to CpuBuilder::seed
+#[cfg(any(not(feature = "std"), target_arch = "wasm32"))]
+fn clock_seed() -> u64 {
+    0
+}
+
+///Why Cpu::run_frame stopped running instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOutcome {
+    ///the whole budget ran
+    Completed,
+    ///the program halted, see Cpu::is_halted
+    Halted,
+    ///a sprite was drawn with the vblank quirk on
+    WaitingForVblank,
+    ///fx0A is waiting for a key
+    WaitingForKey,
+    ///an instruction wrote a watched address, see Cpu::watchpoint_hit
+    Watchpoint,
+}
+
+///What a region of ram holds, see Cpu::memory_regions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    ///the digits pointed at by fx29
+    Font,
+    ///the super-chip digits pointed at by fx30
+    LargeFont,
+    ///the rom, as it was loaded
+    Program,
+    ///nothing was loaded here, programs are free to use it
+    Unused,
+}
+impl core::fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let name = match self {
+            RegionKind::Font => "font",
+            RegionKind::LargeFont => "large font",
+            RegionKind::Program => "program",
+            RegionKind::Unused => "unused",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+///An instruction writing a watched address, see Cpu::add_watchpoint
+#[derive(Debug, Clone, Copy)]
+pub struct WatchpointHit {
+    ///the watched address that was written
+    pub address: u16,
+    ///where the instruction that wrote it is
+    pub program_counter: u16,
+    pub instruction: Instruction,
+}
+impl core::fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04X} was written by {:04X}  {}",
+            self.address, self.program_counter, self.instruction
+        )
+    }
+}
+
+///What the cpu does with an opcode that isn't an instruction in any supported dialect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownOpcodePolicy {
+    ///halts on it, leaving the program counter on it, see Cpu::unknown_opcode
+    #[default]
+    Halt,
+    ///moves past it without running anything, the trace, observer and profile don't see it
+    Skip,
+    ///runs it as a Noop, which the trace, observer and profile do see
+    Noop,
+}
+impl UnknownOpcodePolicy {
+    ///looks up a policy by the name used on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "halt" => Some(UnknownOpcodePolicy::Halt),
+            "skip" => Some(UnknownOpcodePolicy::Skip),
+            "noop" => Some(UnknownOpcodePolicy::Noop),
+            _ => None,
+        }
+    }
+}
+
+///An opcode that isn't an instruction, which halted the cpu, see Cpu::unknown_opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownOpcode {
+    ///where the opcode is
+    pub address: u16,
+    pub opcode: u16,
+}
+impl core::fmt::Display for UnknownOpcode {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04X} at {:04X} is not an instruction",
+            self.opcode, self.address
+        )
+    }
+}
+
+///The state of a cpu at one moment, ram and the display are only kept as a hash. Comparing two
+///of them shows where runs with different settings go their own way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuSnapshot {
+    pub cycles: u64,
+    pub program_counter: u16,
+    pub index: u16,
+    pub registers: [u8; NUM_REGISTERS],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub call_stack: Vec<u16>,
+    pub memory_hash: u64,
+    pub framebuffer_hash: u64,
+}
+
+///A part of the state that differs between two snapshots, holding the value of each
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Cycles(u64, u64),
+    ProgramCounter(u16, u16),
+    Index(u16, u16),
+    ///the register number, then the value in each snapshot
+    Register(u8, u8, u8),
+    DelayTimer(u8, u8),
+    SoundTimer(u8, u8),
+    CallStack(Vec<u16>, Vec<u16>),
+    Memory,
+    Framebuffer,
+}
+impl core::fmt::Display for Difference {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Difference::Cycles(a, b) => write!(f, "cycles: {} vs {}", a, b),
+            Difference::ProgramCounter(a, b) => write!(f, "pc: {:#05X} vs {:#05X}", a, b),
+            Difference::Index(a, b) => write!(f, "I: {:#05X} vs {:#05X}", a, b),
+            Difference::Register(register, a, b) => {
+                write!(f, "V{:X}: {:#04X} vs {:#04X}", register, a, b)
+            }
+            Difference::DelayTimer(a, b) => write!(f, "DT: {} vs {}", a, b),
+            Difference::SoundTimer(a, b) => write!(f, "ST: {} vs {}", a, b),
+            Difference::CallStack(a, b) => write!(f, "stack: {:X?} vs {:X?}", a, b),
+            Difference::Memory => write!(f, "ram differs"),
+            Difference::Framebuffer => write!(f, "display differs"),
+        }
+    }
+}
+
+impl CpuSnapshot {
+    ///Every part of the state that isn't the same in the other snapshot, empty when they match
+    pub fn diff(&self, other: &CpuSnapshot) -> Vec<Difference> {
+        let mut differences = Vec::new();
+        if self.cycles != other.cycles {
+            differences.push(Difference::Cycles(self.cycles, other.cycles));
+        }
+        if self.program_counter != other.program_counter {
+            differences.push(Difference::ProgramCounter(
+                self.program_counter,
+                other.program_counter,
+            ));
+        }
+        if self.index != other.index {
+            differences.push(Difference::Index(self.index, other.index));
+        }
+        for (register, (&a, &b)) in self.registers.iter().zip(&other.registers).enumerate() {
+            if a != b {
+                differences.push(Difference::Register(register as u8, a, b));
+            }
+        }
+        if self.delay_timer != other.delay_timer {
+            differences.push(Difference::DelayTimer(self.delay_timer, other.delay_timer));
+        }
+        if self.sound_timer != other.sound_timer {
+            differences.push(Difference::SoundTimer(self.sound_timer, other.sound_timer));
+        }
+        if self.call_stack != other.call_stack {
+            differences.push(Difference::CallStack(
+                self.call_stack.clone(),
+                other.call_stack.clone(),
+            ));
+        }
+        if self.memory_hash != other.memory_hash {
+            differences.push(Difference::Memory);
+        }
+        if self.framebuffer_hash != other.framebuffer_hash {
+            differences.push(Difference::Framebuffer);
+        }
+        differences
+    }
+}
+
+///64 bit fnv-1a, a small hash that's the same on every platform and needs no std
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+///Configures a Cpu before creating it, everything but the rom is optional
+pub struct CpuBuilder {
+    rom: RomBuffer,
+    quirks: Quirks,
+    framebuffer: Option<Framebuffer>,
+    #[cfg(feature = "std")]
+    trace: Option<Box<dyn std::io::Write>>,
+    seed: Option<u64>,
+    instructions_per_second: u32,
+    font: Option<Vec<u8>>,
+    start_address: usize,
+    refresh_rate: u32,
+    unknown_opcode_policy: UnknownOpcodePolicy,
+}
+
+impl CpuBuilder {
+    pub fn new(rom: RomBuffer) -> Self {
+        CpuBuilder {
+            rom,
+            quirks: Quirks::default(),
+            framebuffer: None,
+            #[cfg(feature = "std")]
+            trace: None,
+            seed: None,
+            instructions_per_second: Chip8Variant::Chip8.instructions_per_second(),
+            font: None,
+            start_address: ROM_START_ADDRESS,
+            refresh_rate: REFRESH_RATE,
+            unknown_opcode_policy: UnknownOpcodePolicy::default(),
+        }
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    ///Replaces the built in font, 5 bytes for each digit from 0 to F, see FONT_SIZE
+    pub fn font(mut self, font: &[u8]) -> Self {
+        self.font = Some(font.to_vec());
+        self
+    }
+
+    ///Starts the display with the given contents instead of blank
+    pub fn framebuffer(mut self, framebuffer: Framebuffer) -> Self {
+        self.framebuffer = Some(framebuffer);
+        self
+    }
+
+    ///Writes the execution trace to the output, see Cpu::enable_trace
+    #[cfg(feature = "std")]
+    pub fn trace(mut self, output: Box<dyn std::io::Write>) -> Self {
+        self.trace = Some(output);
+        self
+    }
+
+    ///Sets how fast Cpu::step_for runs instructions, plain chip8 speed by default
+    pub fn instructions_per_second(mut self, instructions_per_second: u32) -> Self {
+        self.instructions_per_second = instructions_per_second;
+        self
+    }
+
+    ///Sets how many times per second Cpu::step_for ticks the timers, REFRESH_RATE by default
+    pub fn refresh_rate(mut self, refresh_rate: u32) -> Self {
+        self.refresh_rate = refresh_rate.max(1);
+        self
+    }
+
+    ///Decides what happens to opcodes that aren't instructions, halting on them by default
+    pub fn unknown_opcode_policy(mut self, policy: UnknownOpcodePolicy) -> Self {
+        self.unknown_opcode_policy = policy;
+        self
+    }
+
+    ///Seeds the random number generator, without a seed it is seeded from the clock
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    ///Loads the rom at another address than ROM_START_ADDRESS and starts running from there, like
+    ///ETI_660_START_ADDRESS. Fails with the first address past ram when the rom doesn't fit there.
+    pub fn start_address(mut self, address: usize) -> Result<Self, OutOfBounds> {
+        let end = address + self.rom.buffer.len();
+        if address >= RAM_SIZE || end > RAM_SIZE {
+            return Err(OutOfBounds(end));
+        }
+        self.start_address = address;
+        Ok(self)
+    }
+
+    pub fn build(self) -> Cpu {
+        let mut memory = match &self.font {
+            Some(font) => Ram::with_custom_fonts(font),
+            None => Ram::with_fonts(),
+        };
+        for (x, y) in self.rom.buffer.iter().enumerate() {
+            memory.bytes[self.start_address + x] = *y;
+        }
+
+        let display = self.framebuffer.unwrap_or_default();
+        Cpu {
+            display: display.clone(),
+            program_counter: self.start_address as u16,
+            registers: Registers::new(),
+            loaded_memory: memory.clone(),
+            memory,
+            stack: Stack::new(),
+            stackpointer: 0,
+            quirks: self.quirks,
+            #[cfg(feature = "std")]
+            trace: self.trace,
+            halted: false,
+            waiting_for_vblank: false,
+            rng: StdRng::seed_from_u64(self.seed.unwrap_or_else(clock_seed)),
+            observer: None,
+            cycles: 0,
+            keyboard: [false; NUM_KEYS],
+            rpl: [0; 8],
+            instructions_per_second: self.instructions_per_second,
+            instruction_remainder: 0.0,
+            timer_remainder: Duration::ZERO,
+            refresh_rate: self.refresh_rate,
+            decode_cache: None,
+            profile: None,
+            history: VecDeque::new(),
+            history_capacity: 0,
+            start_address: self.start_address as u16,
+            rom_length: self.rom.buffer.len() as u16,
+            dirty_rows: [true; HIGH_RES_DISPLAY_HEIGHT],
+            latest_frame: display.clone(),
+            previous_frame: display,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            last_draw_collisions: 0,
+            collided: false,
+            key_events: VecDeque::new(),
+            frame_hook: None,
+            watchpoints: BTreeSet::new(),
+            watched_write: None,
+            watchpoint_hit: None,
+            key_wait: None,
+            unknown_opcode_policy: self.unknown_opcode_policy,
+            unknown_opcode: None,
+        }
+    }
+}
+
+///A list of every instruction in the chip8 language
+///nnn is a hexadecimal memory address, it's 12 bits long
+///nn is a hexadecimal byte, it's 8 bits
+///n is what's called a "nibble", it's 4 bits
+///X and Y are registers
+#[derive(Debug, Clone, Copy)]
+pub enum Instruction {
+    Noop,                 //0nnn
+    ClearScreen,          //00e0
+    ReturnFromSubroutine, //00ee
+    Exit,                 //00fd, super-chip
+    ScrollDown { n: u8 }, //00cn, super-chip, scrolls the display down n rows
+    ScrollUp { n: u8 },   //00dn, xo-chip, scrolls the display up n rows
+    ScrollRight,          //00fb, super-chip, scrolls the display right 4 pixels
+    ScrollLeft,           //00fc, super-chip, scrolls the display left 4 pixels
+    LowResolution,        //00fe, super-chip, switches to 64x32 pixels and clears the display
+    HighResolution,       //00ff, super-chip, switches to 128x64 pixels and clears the display
+    Jump { nnn: u16 },    //1nnn where nnn is a 12 bit value (lowest 12 bits of the instruction)
+    AddToRegisterX { x: u8, kk: u8 },
+    CallSubroutineAtNNN { nnn: u16 },
+    LoadRegisterX { x: u8, kk: u8 }, //6xkk puts the value kk into Vx
+    LoadXOrYinX { x: u8, y: u8 },    //8xy1
+    LoadXAndYInX { x: u8, y: u8 },   //8xy2
+    LoadXXorYInX { x: u8, y: u8 },   //8xy3
+    AddYToX { x: u8, y: u8 },        //8xy4
+    SubYFromX { x: u8, y: u8 },      //8xy5
+    ShiftXRight1 { x: u8, y: u8 },   //8xy6
+    ShiftXLeft1 { x: u8, y: u8 },    //8xyE
+    SubXFromY { x: u8, y: u8 },      //8xy7
+    LoadRegisterXIntoY { x: u8, y: u8 }, //Stores the value of register Vy in register Vx
+    SetIndexRegister { nnn: u16 },   //ANNN set index register I to nnn
+    JumpToAddressPlusV0 { nnn: u16 }, //BNNN jump to address nnn + v0
+    SkipNextInstructionIfXIsKK { x: u8, kk: u8 }, //skips the next instruction only if the register X holds the value kk
+    SkipNextInstructionIfXIsNotKK { x: u8, kk: u8 }, //same as previous, except skips if register x does not hold value kk
+    SkipNextInstructionIfXIsY { x: u8, y: u8 },
+    SkipNextInstructionIfXIsNotY { x: u8, y: u8 },
+    SetXToRandom { x: u8, kk: u8 },  //cxkk
+    Display { x: u8, y: u8, n: u8 }, //DXYN draws a sprite at coordinate from vx and vy, of width 8 and height n, dxy0 draws 16 by 16 in high resolution
+    SkipIfVxNotPressed { x: u8 },    //exa1
+    SkipIfVxPressed { x: u8 },       //ex9e
+    WaitForKeyPressed { x: u8 },     //fx0a
+    SetXToDelayTimer { x: u8 },      //fx07
+    SetDelayTimerToX { x: u8 },      //Fx15
+    SetSoundTimerToX { x: u8 },      //fx18
+    AddXtoI { x: u8 },               //fx1e
+    SetIToSpriteX { x: u8 },         //fx29
+    SetIToLargeSpriteX { x: u8 },    //fx30, super-chip, points I at the large digit in vx
+    LoadAudioPattern,                //f002, xo-chip, loads the 16 byte sound pattern from I
+    SetPitchToX { x: u8 },           //fx3a, xo-chip, sets the playback rate of the pattern
+    LoadBCDOfX { x: u8 },            //fx33
+    Write0ThroughX { x: u8 },        //fx55
+    Load0ThroughX { x: u8 },         //fx65
+    WriteFlags0ThroughX { x: u8 },   //fx75, super-chip, stores V0 to Vx in the rpl flags
+    LoadFlags0ThroughX { x: u8 },    //fx85, super-chip, loads V0 to Vx from the rpl flags
+}
+
+///Which instruction an Instruction is, without its operands, see Cpu::profile_report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstructionKind {
+    Noop,
+    ClearScreen,
+    ReturnFromSubroutine,
+    Exit,
+    ScrollDown,
+    ScrollUp,
+    ScrollRight,
+    ScrollLeft,
+    LowResolution,
+    HighResolution,
+    Jump,
+    AddToRegisterX,
+    CallSubroutineAtNNN,
+    LoadRegisterX,
+    LoadXOrYinX,
+    LoadXAndYInX,
+    LoadXXorYInX,
+    AddYToX,
+    SubYFromX,
+    ShiftXRight1,
+    ShiftXLeft1,
+    SubXFromY,
+    LoadRegisterXIntoY,
+    SetIndexRegister,
+    JumpToAddressPlusV0,
+    SkipNextInstructionIfXIsKK,
+    SkipNextInstructionIfXIsNotKK,
+    SkipNextInstructionIfXIsY,
+    SkipNextInstructionIfXIsNotY,
+    SetXToRandom,
+    Display,
+    SkipIfVxNotPressed,
+    SkipIfVxPressed,
+    WaitForKeyPressed,
+    SetXToDelayTimer,
+    SetDelayTimerToX,
+    SetSoundTimerToX,
+    AddXtoI,
+    SetIToSpriteX,
+    SetIToLargeSpriteX,
+    LoadAudioPattern,
+    SetPitchToX,
+    LoadBCDOfX,
+    Write0ThroughX,
+    Load0ThroughX,
+    WriteFlags0ThroughX,
+    LoadFlags0ThroughX,
+}
+
+impl Instruction {
+    ///Whether running it can change any pixel of the display, dxyn only changes the rows it
+    ///draws on
+    fn changes_whole_display(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ClearScreen
+                | Instruction::ScrollDown { .. }
+                | Instruction::ScrollUp { .. }
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::LowResolution
+                | Instruction::HighResolution
+        )
+    }
+
+    ///The kind of instruction this is, leaving out its operands
+    pub fn kind(&self) -> InstructionKind {
+        match self {
+            Instruction::Noop => InstructionKind::Noop,
+            Instruction::ClearScreen => InstructionKind::ClearScreen,
+            Instruction::ReturnFromSubroutine => InstructionKind::ReturnFromSubroutine,
+            Instruction::Exit => InstructionKind::Exit,
+            Instruction::ScrollDown { .. } => InstructionKind::ScrollDown,
+            Instruction::ScrollUp { .. } => InstructionKind::ScrollUp,
+            Instruction::ScrollRight => InstructionKind::ScrollRight,
+            Instruction::ScrollLeft => InstructionKind::ScrollLeft,
+            Instruction::LowResolution => InstructionKind::LowResolution,
+            Instruction::HighResolution => InstructionKind::HighResolution,
+            Instruction::Jump { .. } => InstructionKind::Jump,
+            Instruction::AddToRegisterX { .. } => InstructionKind::AddToRegisterX,
+            Instruction::CallSubroutineAtNNN { .. } => InstructionKind::CallSubroutineAtNNN,
+            Instruction::LoadRegisterX { .. } => InstructionKind::LoadRegisterX,
+            Instruction::LoadXOrYinX { .. } => InstructionKind::LoadXOrYinX,
+            Instruction::LoadXAndYInX { .. } => InstructionKind::LoadXAndYInX,
+            Instruction::LoadXXorYInX { .. } => InstructionKind::LoadXXorYInX,
+            Instruction::AddYToX { .. } => InstructionKind::AddYToX,
+            Instruction::SubYFromX { .. } => InstructionKind::SubYFromX,
+            Instruction::ShiftXRight1 { .. } => InstructionKind::ShiftXRight1,
+            Instruction::ShiftXLeft1 { .. } => InstructionKind::ShiftXLeft1,
+            Instruction::SubXFromY { .. } => InstructionKind::SubXFromY,
+            Instruction::LoadRegisterXIntoY { .. } => InstructionKind::LoadRegisterXIntoY,
+            Instruction::SetIndexRegister { .. } => InstructionKind::SetIndexRegister,
+            Instruction::JumpToAddressPlusV0 { .. } => InstructionKind::JumpToAddressPlusV0,
+            Instruction::SkipNextInstructionIfXIsKK { .. } => {
+                InstructionKind::SkipNextInstructionIfXIsKK
+            }
+            Instruction::SkipNextInstructionIfXIsNotKK { .. } => {
+                InstructionKind::SkipNextInstructionIfXIsNotKK
+            }
+            Instruction::SkipNextInstructionIfXIsY { .. } => {
+                InstructionKind::SkipNextInstructionIfXIsY
+            }
+            Instruction::SkipNextInstructionIfXIsNotY { .. } => {
+                InstructionKind::SkipNextInstructionIfXIsNotY
+            }
+            Instruction::SetXToRandom { .. } => InstructionKind::SetXToRandom,
+            Instruction::Display { .. } => InstructionKind::Display,
+            Instruction::SkipIfVxNotPressed { .. } => InstructionKind::SkipIfVxNotPressed,
+            Instruction::SkipIfVxPressed { .. } => InstructionKind::SkipIfVxPressed,
+            Instruction::WaitForKeyPressed { .. } => InstructionKind::WaitForKeyPressed,
+            Instruction::SetXToDelayTimer { .. } => InstructionKind::SetXToDelayTimer,
+            Instruction::SetDelayTimerToX { .. } => InstructionKind::SetDelayTimerToX,
+            Instruction::SetSoundTimerToX { .. } => InstructionKind::SetSoundTimerToX,
+            Instruction::AddXtoI { .. } => InstructionKind::AddXtoI,
+            Instruction::SetIToSpriteX { .. } => InstructionKind::SetIToSpriteX,
+            Instruction::SetIToLargeSpriteX { .. } => InstructionKind::SetIToLargeSpriteX,
+            Instruction::LoadAudioPattern => InstructionKind::LoadAudioPattern,
+            Instruction::SetPitchToX { .. } => InstructionKind::SetPitchToX,
+            Instruction::LoadBCDOfX { .. } => InstructionKind::LoadBCDOfX,
+            Instruction::Write0ThroughX { .. } => InstructionKind::Write0ThroughX,
+            Instruction::Load0ThroughX { .. } => InstructionKind::Load0ThroughX,
+            Instruction::WriteFlags0ThroughX { .. } => InstructionKind::WriteFlags0ThroughX,
+            Instruction::LoadFlags0ThroughX { .. } => InstructionKind::LoadFlags0ThroughX,
+        }
+    }
+
+    ///Decodes an opcode, returns None for opcodes that aren't part of any supported dialect
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let instruction = match first_nibble(opcode) {
+            0x0 => match opcode {
+                0x00E0 => Instruction::ClearScreen,
+                0x00EE => Instruction::ReturnFromSubroutine,
+                0x00C0..=0x00CF => Instruction::ScrollDown {
+                    n: fourth_nibble(opcode),
+                },
+                0x00D0..=0x00DF => Instruction::ScrollUp {
+                    n: fourth_nibble(opcode),
+                },
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowResolution,
+                0x00FF => Instruction::HighResolution,
+                //0nnn calls a machine code routine of the cosmac vip, which can't be emulated
+                _ => Instruction::Noop,
+            },
+            0x1 => Instruction::Jump { nnn: oxxx(opcode) },
+            0x2 => Instruction::CallSubroutineAtNNN { nnn: oxxx(opcode) },
+            0x3 => Instruction::SkipNextInstructionIfXIsKK {
+                x: second_nibble(opcode),
+                kk: last_byte(opcode),
+            },
+            0x4 => Instruction::SkipNextInstructionIfXIsNotKK {
+                x: second_nibble(opcode),
+                kk: last_byte(opcode),
+            },
+            0x5 => Instruction::SkipNextInstructionIfXIsY {
+                x: second_nibble(opcode),
+                y: third_nibble(opcode),
+            },
+            0x6 => Instruction::LoadRegisterX {
+                x: second_nibble(opcode),
+                kk: last_byte(opcode),
+            },
+            0x7 => Instruction::AddToRegisterX {
+                x: second_nibble(opcode),
+                kk: last_byte(opcode),
+            },
+            0x8 => match fourth_nibble(opcode) {
+                0x0 => Instruction::LoadRegisterXIntoY {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                0x1 => Instruction::LoadXOrYinX {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                0x2 => Instruction::LoadXAndYInX {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                0x3 => Instruction::LoadXXorYInX {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+
+                0x4 => Instruction::AddYToX {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                0x5 => Instruction::SubYFromX {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                0x6 => Instruction::ShiftXRight1 {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                0x7 => Instruction::SubXFromY {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+
+                0xE => Instruction::ShiftXLeft1 {
+                    x: second_nibble(opcode),
+                    y: third_nibble(opcode),
+                },
+                _ => return None,
+            },
+            0x9 => Instruction::SkipNextInstructionIfXIsNotY {
+                x: second_nibble(opcode),
+                y: third_nibble(opcode),
+            },
+            0xA => Instruction::SetIndexRegister { nnn: oxxx(opcode) },
+            0xB => Instruction::JumpToAddressPlusV0 { nnn: oxxx(opcode) },
+            0xC => Instruction::SetXToRandom {
+                x: second_nibble(opcode),
+                kk: last_byte(opcode),
+            },
+            0xD => Instruction::Display {
+                x: second_nibble(opcode),
+                y: third_nibble(opcode),
+                n: fourth_nibble(opcode),
+            },
+            0xE => match last_byte(opcode) {
+                0xA1 => Instruction::SkipIfVxNotPressed {
+                    x: second_nibble(opcode),
+                },
+                0x9E => Instruction::SkipIfVxPressed {
+                    x: second_nibble(opcode),
+                },
+                _ => return None,
+            },
+            0xF => match last_byte(opcode) {
+                0x0A => Instruction::WaitForKeyPressed {
+                    x: second_nibble(opcode),
+                },
+                0x07 => Instruction::SetXToDelayTimer {
+                    x: second_nibble(opcode),
+                },
+                0x15 => Instruction::SetDelayTimerToX {
+                    x: second_nibble(opcode),
+                },
+                0x18 => Instruction::SetSoundTimerToX {
+                    x: second_nibble(opcode),
+                },
+                0x1E => Instruction::AddXtoI {
+                    x: second_nibble(opcode),
+                },
+                0x29 => Instruction::SetIToSpriteX {
+                    x: second_nibble(opcode),
+                },
+                0x30 => Instruction::SetIToLargeSpriteX {
+                    x: second_nibble(opcode),
+                },
+                0x33 => Instruction::LoadBCDOfX {
+                    x: second_nibble(opcode),
+                },
+                0x55 => Instruction::Write0ThroughX {
+                    x: second_nibble(opcode),
+                },
+                0x65 => Instruction::Load0ThroughX {
+                    x: second_nibble(opcode),
+                },
+                0x75 => Instruction::WriteFlags0ThroughX {
+                    x: second_nibble(opcode),
+                },
+                0x85 => Instruction::LoadFlags0ThroughX {
+                    x: second_nibble(opcode),
+                },
+                0x02 if opcode == 0xF002 => Instruction::LoadAudioPattern,
+                0x3A => Instruction::SetPitchToX {
+                    x: second_nibble(opcode),
+                },
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+}
+
+//returns the first 4 bits of the opcode as a byte
+fn first_nibble(opcode: u16) -> u8 {
+    ((opcode >> 12) & 0xF) as u8
+}
+//returns the second 4 bits of the opcode as a byte
+fn second_nibble(opcode: u16) -> u8 {
+    ((opcode >> 8) & 0xf) as u8
+}
+fn third_nibble(opcode: u16) -> u8 {
+    ((opcode >> 4) & 0xf) as u8
+}
+fn fourth_nibble(opcode: u16) -> u8 {
+    (opcode as u8) & 0xf
+}
+//returns the last byte
+fn last_byte(code: u16) -> u8 {
+    (code & 0xff) as u8
+}
+fn oxxx(code: u16) -> u16 {
+    code & 0xfff
+}
+
+///Formats the instruction in the usual chip8 assembly mnemonics, e.g. `DRW V1, V2, 5`
+impl core::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            Instruction::Noop => write!(f, "NOP"),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::ReturnFromSubroutine => write!(f, "RET"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::LowResolution => write!(f, "LOW"),
+            Instruction::HighResolution => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP {:#05X}", nnn),
+            Instruction::CallSubroutineAtNNN { nnn } => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SkipNextInstructionIfXIsKK { x, kk } => {
+                write!(f, "SE V{:X}, {:#04X}", x, kk)
+            }
+            Instruction::SkipNextInstructionIfXIsNotKK { x, kk } => {
+                write!(f, "SNE V{:X}, {:#04X}", x, kk)
+            }
+            Instruction::SkipNextInstructionIfXIsY { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LoadRegisterX { x, kk } => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::AddToRegisterX { x, kk } => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::LoadRegisterXIntoY { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::LoadXOrYinX { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::LoadXAndYInX { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::LoadXXorYInX { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddYToX { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubYFromX { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftXRight1 { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubXFromY { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftXLeft1 { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNextInstructionIfXIsNotY { x, y } => {
+                write!(f, "SNE V{:X}, V{:X}", x, y)
+            }
+            Instruction::SetIndexRegister { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JumpToAddressPlusV0 { nnn } => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::SetXToRandom { x, kk } => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::Display { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfVxPressed { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfVxNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::SetXToDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKeyPressed { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelayTimerToX { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSoundTimerToX { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddXtoI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::SetIToSpriteX { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::SetIToLargeSpriteX { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::LoadAudioPattern => write!(f, "AUDIO"),
+            Instruction::SetPitchToX { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::LoadBCDOfX { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Write0ThroughX { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load0ThroughX { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::WriteFlags0ThroughX { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags0ThroughX { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///A cpu running the program with the given quirks, seeded so runs repeat
+    fn cpu_with(program: &[u8], quirks: Quirks) -> Cpu {
+        let rom = RomBuffer::from_bytes(program.to_vec()).unwrap();
+        CpuBuilder::new(rom).quirks(quirks).seed(0).build()
+    }
+
+    fn cpu(program: &[u8]) -> Cpu {
+        cpu_with(program, Quirks::chip8())
+    }
+
+    fn run(cpu: &mut Cpu, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.cycle().unwrap();
+        }
+    }
+
+    #[test]
+    fn writes_past_the_end_of_ram_are_errors() {
+        //I := 0xFFF, then bcd, save v3 and load v3, each needs more than one byte
+        for opcode in [[0xF0, 0x33], [0xF3, 0x55], [0xF3, 0x65]] {
+            let mut cpu = cpu(&[0xAF, 0xFF, opcode[0], opcode[1]]);
+            run(&mut cpu, 1);
+            assert!(matches!(
+                cpu.cycle(),
+                Err(CpuError::OutOfBounds(OutOfBounds(0x1000)))
+            ));
+        }
+    }
+
+    #[test]
+    fn i_wraps_instead_of_overflowing() {
+        //v0 := 0xFF, I := 0xFFF, then I += v0 in a loop until it passes 0xFFFF
+        let mut cpu = cpu(&[0x60, 0xFF, 0xAF, 0xFF, 0xF0, 0x1E, 0x12, 0x04]);
+        run(&mut cpu, 2 + 2 * 300);
+        assert_eq!(
+            cpu.index_register(),
+            ((0xFFF + 300 * 0xFF) % 0x10000) as u16
+        );
+    }
+
+    #[test]
+    fn returning_without_a_subroutine_is_an_error() {
+        let mut cpu = cpu(&[0x00, 0xEE]);
+        assert!(matches!(cpu.cycle(), Err(CpuError::StackUnderflow(0x200))));
+    }
+
+    #[test]
+    fn calling_past_the_top_of_the_stack_is_an_error() {
+        //a subroutine at 0x200 that calls itself
+        let mut cpu = cpu(&[0x22, 0x00]);
+        run(&mut cpu, STACK_SIZE);
+        assert!(matches!(cpu.cycle(), Err(CpuError::StackOverflow(0x200))));
+    }
+
+    ///A trace output the test can still read after handing it to the cpu
+    #[cfg(feature = "std")]
+    #[derive(Clone, Default)]
+    struct SharedOutput(std::rc::Rc<core::cell::RefCell<Vec<u8>>>);
+    #[cfg(feature = "std")]
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn traces_the_ibm_logo() {
+        let output = SharedOutput::default();
+        let mut cpu = cpu(include_bytes!("../ibmlogo.ch8"));
+        cpu.enable_trace(output.clone());
+        run(&mut cpu, 4);
+        let trace = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(
+            trace,
+            "0200: 00E0  CLS\n\
+             0202: A22A  LD I, 0x22A\n\
+             0204: 600C  LD V0, 0x0C\n\
+             0206: 6108  LD V1, 0x08\n"
+        );
+    }
+
+    ///vf := 7, va := 5, I := the address, I += va
+    fn add_to_i_from(address: u16, i_overflow: bool) -> Cpu {
+        let mut quirks = Quirks::chip8();
+        quirks.i_overflow = i_overflow;
+        let [high, low] = (0xA000 | address).to_be_bytes();
+        let mut cpu = cpu_with(&[0x6F, 0x07, 0x6A, 0x05, high, low, 0xFA, 0x1E], quirks);
+        run(&mut cpu, 4);
+        cpu
+    }
+
+    #[test]
+    fn i_overflow_sets_vf_past_0xfff() {
+        let cpu = add_to_i_from(0xFFE, true);
+        assert_eq!(cpu.index_register(), 0x1003);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 1);
+
+        let cpu = add_to_i_from(0xFF0, true);
+        assert_eq!(cpu.index_register(), 0xFF5);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 0);
+    }
+
+    #[test]
+    fn without_i_overflow_vf_is_left_alone() {
+        let cpu = add_to_i_from(0xFFE, false);
+        assert_eq!(cpu.index_register(), 0x1003);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 7);
+    }
+
+    ///Sprites are drawn without waiting for the vertical blank
+    fn drawing_cpu(program: &[u8], wrap: bool) -> Cpu {
+        let mut quirks = Quirks::chip8();
+        quirks.vblank = false;
+        quirks.wrap = wrap;
+        cpu_with(program, quirks)
+    }
+
+    #[test]
+    fn redrawing_a_sprite_erases_it_and_sets_vf() {
+        //the 0 from the font at 0,0, drawn twice
+        let program = [0x60, 0x00, 0x61, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0xD0, 0x15];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 0);
+        assert!(cpu.framebuffer().is_on(0, 0));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 1);
+        assert!(cpu.framebuffer().is_blank());
+    }
+
+    #[test]
+    fn sprites_side_by_side_leave_vf_clear() {
+        //the 0 from the font at 0,0 and at 8,0
+        let program = [
+            0x60, 0x00, 0x61, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0x62, 0x08, 0xD2, 0x15,
+        ];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 6);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 0);
+        assert!(cpu.framebuffer().is_on(0, 0));
+        assert!(cpu.framebuffer().is_on(8, 0));
+    }
+
+    #[test]
+    fn clipped_pixels_do_not_collide() {
+        //the 0 from the font at 62,0, half of it past the right edge, then at 0,0 where the
+        //other half would have gone with the wrap quirk
+        let program = [0x60, 0x3E, 0x61, 0x00, 0xF1, 0x29, 0xD0, 0x15, 0xD1, 0x15];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 4);
+        assert!(cpu.framebuffer().is_on(62, 0));
+        assert!(cpu.framebuffer().is_on(63, 0));
+        assert!(!cpu.framebuffer().is_on(0, 0));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 0);
+
+        let mut cpu = drawing_cpu(&program, true);
+        run(&mut cpu, 4);
+        assert!(cpu.framebuffer().is_on(0, 0));
+        assert!(cpu.framebuffer().is_on(1, 0));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 1);
+    }
+
+    #[test]
+    fn call_stack_lists_the_return_addresses_outermost_first() {
+        //0x200 calls 0x204, which calls 0x208, which returns
+        let program = [0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0x00, 0x00, 0xEE];
+        let mut cpu = cpu(&program);
+        assert!(cpu.call_stack().is_empty());
+        run(&mut cpu, 2);
+        assert_eq!(cpu.call_stack(), vec![0x202, 0x206]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.call_stack(), vec![0x202]);
+    }
+
+    #[test]
+    fn octo_source_runs() {
+        let rom = RomBuffer::from_octo(": main\n  v3 := 7\n  v3 += 2").unwrap();
+        let mut cpu = CpuBuilder::new(rom).build();
+        //the jump to main first
+        run(&mut cpu, 3);
+        assert_eq!(cpu.get_register(3).unwrap(), 9);
+    }
+
+    #[test]
+    fn delay_timer_reads_back_what_was_set_in_the_same_frame() {
+        //v0 := 0x25, delay := v0, v1 := delay
+        let mut cpu = cpu(&[0x60, 0x25, 0xF0, 0x15, 0xF1, 0x07]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.get_register(1).unwrap(), 0x25);
+        cpu.vblank();
+        assert_eq!(cpu.delay_timer(), 0x24);
+    }
+
+    #[test]
+    fn sound_timer_only_counts_down_at_vblank() {
+        //v0 := 3, buzzer := v0, then a few v1 += 1
+        let program = [
+            0x60, 0x03, 0xF0, 0x18, 0x71, 0x01, 0x71, 0x01, 0x71, 0x01, 0x71, 0x01,
+        ];
+        let mut cpu = cpu(&program);
+        run(&mut cpu, 2);
+        for _ in 0..3 {
+            assert_eq!(cpu.sound_timer(), 3);
+            assert!(cpu.is_beeping());
+            run(&mut cpu, 1);
+        }
+        assert_eq!(cpu.sound_timer(), 3);
+        cpu.vblank();
+        assert_eq!(cpu.sound_timer(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn assembled_roms_survive_saving_and_loading() {
+        let source = ": main\n  v0 := 1\n  i := main\n  sprite v0 v0 2\n  exit";
+        let directory = std::env::temp_dir();
+        let octo = directory.join(format!("chip8_round_trip_{}.8o", std::process::id()));
+        let rom = directory.join(format!("chip8_round_trip_{}.ch8", std::process::id()));
+        std::fs::write(&octo, source).unwrap();
+
+        let assembled = RomBuffer::new(&octo).unwrap();
+        assembled.save(&rom).unwrap();
+        let reloaded = RomBuffer::new(&rom).unwrap();
+        std::fs::remove_file(&octo).unwrap();
+        std::fs::remove_file(&rom).unwrap();
+
+        assert_eq!(assembled.buffer, assembler::assemble(source).unwrap());
+        assert_eq!(reloaded.buffer, assembled.buffer);
+    }
+
+    #[test]
+    fn the_wrap_quirk_decides_where_pixels_past_the_edges_go() {
+        //the 0 from the font at 62,30, so it straddles both the right and the bottom edge
+        let program = [0x60, 0x3E, 0x61, 0x1E, 0xF2, 0x29, 0xD0, 0x15];
+        let mut cpu = drawing_cpu(&program, true);
+        run(&mut cpu, 4);
+        let display = cpu.framebuffer();
+        //the top row of the 0 goes on at the left edge
+        assert!(display.is_on(62, 30) && display.is_on(63, 30));
+        assert!(display.is_on(0, 30) && display.is_on(1, 30));
+        //its third row lands on the top row of the display, the last one on the third
+        assert!(display.is_on(62, 0) && !display.is_on(0, 0) && display.is_on(1, 0));
+        assert!(display.is_on(0, 2) && display.is_on(1, 2));
+
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 4);
+        let display = cpu.framebuffer();
+        assert!(display.is_on(62, 30) && display.is_on(63, 30) && display.is_on(62, 31));
+        assert_eq!(display.pixels().filter(|&pixel| pixel != 0).count(), 3);
+    }
+
+    #[test]
+    fn odd_length_roms_end_in_an_opcode_with_a_zero_low_byte() {
+        //v1 := 5, then a lone 0x61 that becomes v1 := 0
+        let rom = RomBuffer::from_bytes(vec![0x61, 0x05, 0x61]).unwrap();
+        assert_eq!(rom.buffer, vec![0x61, 0x05, 0x61, 0x00]);
+        let mut cpu = CpuBuilder::new(rom).build();
+        run(&mut cpu, 1);
+        assert!(matches!(
+            cpu.peek_instruction(),
+            Some(Instruction::LoadRegisterX { x: 1, kk: 0 })
+        ));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_register(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn counts_the_instructions_it_ran() {
+        //v0 += 1 in a loop
+        let mut cpu = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        run(&mut cpu, 100);
+        assert_eq!(cpu.cycle_count(), 100);
+        cpu.reset();
+        assert_eq!(cpu.cycle_count(), 0);
+    }
+
+    #[test]
+    fn sprites_go_to_x_across_and_y_down() {
+        //an L three rows tall drawn at 10,3, the sprite follows the code at 0x208
+        let program = [
+            0x60, 0x0A, 0x61, 0x03, 0xA2, 0x08, 0xD0, 0x13, 0x80, 0x80, 0xE0,
+        ];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 4);
+        let display = cpu.framebuffer();
+        let lit: Vec<(usize, usize)> = (0..DISPLAY_HEIGHT)
+            .flat_map(|y| (0..DISPLAY_WIDTH).map(move |x| (x, y)))
+            .filter(|&(x, y)| display.is_on(x, y))
+            .collect();
+        assert_eq!(lit, vec![(10, 3), (10, 4), (10, 5), (11, 5), (12, 5)]);
+    }
+
+    #[test]
+    fn sprite_data_does_not_change_the_detected_format() {
+        //the ibm logo has 00 FF in its sprites and custom.ch8 has F0 00
+        for rom in [
+            &include_bytes!("../ibmlogo.ch8")[..],
+            include_bytes!("../testlogo.ch8"),
+            include_bytes!("../custom.ch8"),
+        ] {
+            let rom = RomBuffer::from_bytes(rom.to_vec()).unwrap();
+            assert_eq!(rom.detect_format(), Chip8Variant::Chip8);
+        }
+    }
+
+    #[test]
+    fn detects_extended_opcodes_the_program_reaches() {
+        //a skip over a call to 0x20A, which scrolls and returns
+        let rom = RomBuffer::from_bytes(vec![
+            0x00, 0xE0, 0x30, 0x00, 0x22, 0x0A, 0x12, 0x06, 0x00, 0x00, 0x00, 0xC4, 0x00, 0xEE,
+        ])
+        .unwrap();
+        assert_eq!(rom.detect_format(), Chip8Variant::SuperChip);
+        //a loop jumping over what would be xo-chip's f000
+        let rom = RomBuffer::from_bytes(vec![0x12, 0x00, 0xF0, 0x00]).unwrap();
+        assert_eq!(rom.detect_format(), Chip8Variant::Chip8);
+        //a skip over xo-chip's 4 byte f000
+        let rom = RomBuffer::from_bytes(vec![
+            0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFB, 0x12, 0x08,
+        ])
+        .unwrap();
+        assert_eq!(rom.detect_format(), Chip8Variant::XoChip);
+    }
+
+    #[test]
+    fn exit_halts_the_program() {
+        //v0 := 5, exit
+        let mut cpu = cpu(&[0x60, 0x05, 0x00, 0xFD]);
+        run(&mut cpu, 2);
+        assert!(cpu.is_halted());
+        //it stays on the exit
+        assert_eq!(cpu.program_counter(), 0x202);
+        run(&mut cpu, 3);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn rpl_flags_keep_registers_until_they_are_loaded_back() {
+        //v0 to v2 := 1, 2, 3, save them in the flags, clear them, load only v0 and v1 back
+        let program = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00,
+            0xF1, 0x85,
+        ];
+        let mut cpu = cpu_with(&program, Quirks::superchip());
+        run(&mut cpu, 8);
+        assert_eq!(cpu.get_register(0).unwrap(), 1);
+        assert_eq!(cpu.get_register(1).unwrap(), 2);
+        assert_eq!(cpu.get_register(2).unwrap(), 0);
+    }
+
+    #[test]
+    fn fx30_points_i_at_the_large_digit() {
+        //v3 := 2, I := the large 2
+        let mut cpu = cpu_with(&[0x63, 0x02, 0xF3, 0x30], Quirks::superchip());
+        run(&mut cpu, 2);
+        let address = cpu.index_register();
+        assert_eq!(address as usize, LARGE_FONT_ADDRESS + 2 * 10);
+        let glyph: Vec<u8> = (address..address + 10)
+            .map(|address| cpu.get_memory(address).unwrap())
+            .collect();
+        assert_eq!(glyph, LARGE_FONT[20..30]);
+    }
+
+    #[test]
+    fn release_all_keys_lets_go_of_every_key() {
+        let mut cpu = cpu(&[]);
+        for key in [0x1, 0x5, 0xA, 0xF] {
+            cpu.set_key_state(key, true);
+        }
+        assert_eq!(cpu.pressed_keys().count(), 4);
+        cpu.release_all_keys();
+        assert_eq!(cpu.pressed_keys().count(), 0);
+    }
+
+    #[test]
+    fn peeking_does_not_run_the_instruction() {
+        //v0 := 5, then a draw
+        let mut cpu = cpu(&[0x60, 0x05, 0xD0, 0x05]);
+        run(&mut cpu, 1);
+        let before = cpu.snapshot();
+        assert!(matches!(
+            cpu.peek_instruction(),
+            Some(Instruction::Display { x: 0, y: 0, n: 5 })
+        ));
+        assert_eq!(cpu.snapshot(), before);
+        assert_eq!(cpu.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn roms_loaded_at_0x600_start_running_there() {
+        //v4 := 0x66
+        let rom = RomBuffer::from_bytes(vec![0x64, 0x66]).unwrap();
+        let mut cpu = CpuBuilder::new(rom)
+            .start_address(ETI_660_START_ADDRESS)
+            .unwrap()
+            .build();
+        assert_eq!(cpu.program_counter(), 0x600);
+        assert_eq!(cpu.get_memory(0x600).unwrap(), 0x64);
+        assert_eq!(cpu.get_memory(0x200).unwrap(), 0);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.get_register(4).unwrap(), 0x66);
+        assert_eq!(cpu.program_counter(), 0x602);
+    }
+
+    #[test]
+    fn reset_forgets_collisions() {
+        //the 0 from the font at 0,0, drawn twice
+        let program = [0x60, 0x00, 0x61, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0xD0, 0x15];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 5);
+        assert_eq!(cpu.last_draw_collisions(), 1);
+        cpu.reset();
+        assert_eq!(cpu.last_draw_collisions(), 0);
+        assert!(!cpu.take_collision());
+    }
+
+    #[test]
+    fn the_logic_quirk_resets_vf_after_8xy1_8xy2_and_8xy3() {
+        //v0 |= v1, v0 &= v1 and v0 ^= v1 with v0 = 0x0C and v1 = 0x0A
+        for (low_byte, result) in [(0x11, 0x0E), (0x12, 0x08), (0x13, 0x06)] {
+            for logic in [true, false] {
+                let mut quirks = Quirks::chip8();
+                quirks.logic = logic;
+                let program = [0x6F, 0x01, 0x60, 0x0C, 0x61, 0x0A, 0x80, low_byte];
+                let mut cpu = cpu_with(&program, quirks);
+                run(&mut cpu, 4);
+                assert_eq!(cpu.get_register(0).unwrap(), result);
+                let flag = if logic { 0 } else { 1 };
+                assert_eq!(cpu.get_register(0xF).unwrap(), flag);
+            }
+        }
+    }
+
+    #[test]
+    fn drawing_marks_exactly_the_rows_it_touched() {
+        //the 0 from the font at 10,3
+        let program = [0x60, 0x0A, 0x61, 0x03, 0xF2, 0x29, 0xD0, 0x15];
+        let mut cpu = drawing_cpu(&program, false);
+        //a new cpu starts with every row dirty
+        assert_eq!(cpu.take_dirty_rows().count(), DISPLAY_HEIGHT);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.take_dirty_rows().count(), 0);
+        run(&mut cpu, 1);
+        assert_eq!(
+            cpu.take_dirty_rows().collect::<Vec<_>>(),
+            vec![3, 4, 5, 6, 7]
+        );
+        assert_eq!(cpu.take_dirty_rows().count(), 0);
+        assert!(!cpu.take_display_dirty());
+    }
+
+    #[test]
+    fn stepping_back_restores_the_display_only_instructions_changed() {
+        //the 0 from the font at 0,0, then v0 += 1
+        let program = [0x60, 0x00, 0x61, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0x70, 0x01];
+        let mut cpu = drawing_cpu(&program, false);
+        cpu.set_history(10);
+        run(&mut cpu, 5);
+        let drawn = cpu.framebuffer().clone();
+        //only the draw kept the 5 rows it drew on
+        let rows: Vec<usize> = cpu
+            .history
+            .iter()
+            .flat_map(|state| state.display_rows.iter().map(|row| row.0))
+            .collect();
+        assert_eq!(rows, vec![0, 1, 2, 3, 4]);
+        assert!(cpu.history.iter().all(|state| state.display.is_none()));
+        assert!(cpu.step_back());
+        assert_eq!(cpu.framebuffer(), &drawn);
+        assert!(cpu.step_back());
+        assert!(cpu.framebuffer().is_blank());
+    }
+
+    #[test]
+    fn run_until_stops_before_the_address() {
+        //v0 += 1 over and over, the one at 0x210 must not run
+        let mut cpu = cpu(&[0x70, 0x01].repeat(16));
+        assert_eq!(cpu.run_until(until::address(0x210), 100).unwrap(), 8);
+        assert_eq!(cpu.program_counter(), 0x210);
+        assert_eq!(cpu.get_register(0).unwrap(), 8);
+        //it already holds, so nothing more runs
+        assert_eq!(cpu.run_until(until::address(0x210), 100).unwrap(), 0);
+    }
+
+    #[test]
+    fn display_opcodes_move_or_clear_a_pixel() {
+        //a pixel drawn at 8,8 from the sprite at 0x20C, then the opcode
+        for (opcode, lit, dimensions) in [
+            (0x00C3, Some((8, 11)), (64, 32)),
+            (0x00D3, Some((8, 5)), (64, 32)),
+            (0x00FB, Some((12, 8)), (64, 32)),
+            (0x00FC, Some((4, 8)), (64, 32)),
+            (0x00FE, None, (64, 32)),
+            (0x00FF, None, (128, 64)),
+        ] {
+            let [high, low] = u16::to_be_bytes(opcode);
+            let program = [
+                0x60, 0x08, 0x61, 0x08, 0xA2, 0x0C, 0xD0, 0x11, high, low, 0x12, 0x0A, 0x80, 0x00,
+            ];
+            let mut cpu = drawing_cpu(&program, false);
+            run(&mut cpu, 5);
+            let fb = cpu.framebuffer();
+            let count = fb.pixels().filter(|&pixel| pixel != 0).count();
+            assert_eq!(count, lit.iter().count(), "{:04X}", opcode);
+            if let Some((x, y)) = lit {
+                assert!(fb.is_on(x, y), "{:04X}", opcode);
+            }
+            assert_eq!(cpu.display_dimensions(), dimensions, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn a_queued_key_press_is_seen_by_the_next_skip() {
+        //v0 := 5, skip the next instruction if key 5 is held
+        let program = [0x60, 0x05, 0xE0, 0x9E, 0x61, 0x01];
+        let mut pressed = cpu(&program);
+        pressed.queue_key_events(vec![(1, 5, true)]);
+        run(&mut pressed, 1);
+        assert!(!pressed.key_state(5));
+        run(&mut pressed, 1);
+        assert!(pressed.key_state(5));
+        assert_eq!(pressed.program_counter(), 0x206);
+
+        //without the press nothing is skipped
+        let mut unpressed = cpu(&program);
+        run(&mut unpressed, 2);
+        assert_eq!(unpressed.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn register_16_is_rejected() {
+        let mut cpu = cpu(&[0x6F, 0x07]);
+        run(&mut cpu, 1);
+        let before = cpu.snapshot();
+        assert!(matches!(cpu.get_register(16), Err(InvalidRegister(16))));
+        assert!(matches!(cpu.set_register(16, 1), Err(InvalidRegister(16))));
+        assert_eq!(cpu.snapshot(), before);
+        assert_eq!(cpu.get_register(0xF).unwrap(), 7);
+    }
+
+    #[test]
+    fn fetching_past_the_end_of_ram_is_an_error() {
+        //the last whole opcode of ram runs, 0000 does nothing, the fetch after it is past the end
+        let mut ran_off = cpu(&[0x00, 0xE0]);
+        ran_off.set_program_counter(0xFFE).unwrap();
+        run(&mut ran_off, 1);
+        assert_eq!(ran_off.program_counter(), 0x1000);
+        assert!(matches!(
+            ran_off.cycle(),
+            Err(CpuError::OutOfBounds(OutOfBounds(0x1000)))
+        ));
+
+        //a jump to the last byte only has half an opcode to fetch
+        let mut jumped = cpu(&[0x1F, 0xFF]);
+        run(&mut jumped, 1);
+        assert!(matches!(
+            jumped.cycle(),
+            Err(CpuError::OutOfBounds(OutOfBounds(0x1000)))
+        ));
+    }
+
+    #[test]
+    fn quirk_presets() {
+        let chip8 = Quirks {
+            shift: false,
+            memory: true,
+            jump: false,
+            i_overflow: false,
+            vblank: true,
+            wrap: false,
+            logic: true,
+            key_release: true,
+        };
+        let superchip = Quirks {
+            shift: true,
+            memory: false,
+            jump: true,
+            i_overflow: false,
+            vblank: false,
+            wrap: false,
+            logic: false,
+            key_release: false,
+        };
+        let superchip_legacy = Quirks {
+            vblank: true,
+            ..superchip
+        };
+        let xochip = Quirks {
+            shift: false,
+            memory: true,
+            jump: false,
+            i_overflow: false,
+            vblank: false,
+            wrap: true,
+            logic: false,
+            key_release: true,
+        };
+        assert_eq!(Quirks::chip8(), chip8);
+        assert_eq!(Quirks::superchip(), superchip);
+        assert_eq!(Quirks::superchip_modern(), superchip);
+        assert_eq!(Quirks::superchip_legacy(), superchip_legacy);
+        assert_eq!(Quirks::xochip(), xochip);
+        assert_eq!(Quirks::from_name("chip8"), Some(chip8));
+        assert_eq!(Quirks::from_name("superchip"), Some(superchip));
+        assert_eq!(
+            Quirks::from_name("superchip-legacy"),
+            Some(superchip_legacy)
+        );
+        assert_eq!(Quirks::from_name("xochip"), Some(xochip));
+        assert_eq!(Quirks::from_name("chip48"), None);
+    }
+
+    #[test]
+    fn the_program_counter_stays_inside_ram() {
+        let mut cpu = cpu(&[]);
+        for address in [0xFFF, 0x1000, 0xFFFF] {
+            assert!(matches!(
+                cpu.set_program_counter(address),
+                Err(OutOfBounds(refused)) if refused == address as usize
+            ));
+            assert_eq!(cpu.program_counter(), 0x200);
+        }
+        //the last address with a whole opcode after it
+        cpu.set_program_counter(0xFFE).unwrap();
+        assert_eq!(cpu.program_counter(), 0xFFE);
+    }
+
+    #[test]
+    fn fx55_into_a_watched_address_stops_the_run() {
+        //v0 to v3 := 1 to 4, I := 0x300, save v0 to v3, then v5 := 5 which must not run
+        let mut cpu = cpu(&[
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0xA3, 0x00, 0xF3, 0x55, 0x65, 0x05,
+        ]);
+        cpu.add_watchpoint(0x302);
+        assert_eq!(cpu.run_until(|_| false, 100).unwrap(), 6);
+        let hit = cpu.watchpoint_hit().unwrap();
+        assert_eq!(hit.address, 0x302);
+        assert_eq!(hit.program_counter, 0x20A);
+        assert!(matches!(
+            hit.instruction,
+            Instruction::Write0ThroughX { x: 3 }
+        ));
+        assert_eq!(cpu.get_memory(0x302).unwrap(), 3);
+        assert_eq!(cpu.get_register(5).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_key_held_before_fx0a_has_to_be_let_go_first() {
+        //v0 := the key, with key 3 held from the start
+        let mut cpu = cpu(&[0xF0, 0x0A]);
+        cpu.set_key_state(3, true);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.program_counter(), 0x200);
+        //letting go of the held key doesn't count as a press
+        cpu.set_key_state(3, false);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.program_counter(), 0x200);
+        cpu.set_key_state(3, true);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.program_counter(), 0x200);
+        cpu.set_key_state(3, false);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert_eq!(cpu.get_register(0).unwrap(), 3);
+
+        //without the quirk the held key ends the wait right away
+        let mut quirks = Quirks::chip8();
+        quirks.key_release = false;
+        let mut held = cpu_with(&[0xF0, 0x0A], quirks);
+        held.set_key_state(3, true);
+        run(&mut held, 1);
+        assert_eq!(held.program_counter(), 0x202);
+        assert_eq!(held.get_register(0).unwrap(), 3);
+    }
+
+    #[test]
+    fn render_ascii_puts_x_in_columns_and_y_in_rows() {
+        //high resolution, a single pixel from the sprite at 0x20C drawn at 60,2
+        let program = [
+            0x00, 0xFF, 0x60, 0x3C, 0x61, 0x02, 0xA2, 0x0C, 0xD0, 0x11, 0x12, 0x0A, 0x80, 0x00,
+        ];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 5);
+        let text = render_ascii(cpu.framebuffer());
+        let rows: Vec<Vec<char>> = text.lines().map(|row| row.chars().collect()).collect();
+        assert_eq!(rows.len(), 64);
+        assert!(rows.iter().all(|row| row.len() == 128));
+        assert_eq!(rows[2][60], '█');
+        assert_eq!(rows[60][2], ' ');
+        assert_eq!(text.matches('█').count(), 1);
+    }
+
+    #[test]
+    fn empty_and_single_byte_roms_load() {
+        //nothing to run but the zeroed ram after it, 0000 does nothing
+        let rom = RomBuffer::from_bytes(Vec::new()).unwrap();
+        assert!(rom.buffer.is_empty());
+        let mut empty = CpuBuilder::new(rom).build();
+        assert!(matches!(empty.peek_instruction(), Some(Instruction::Noop)));
+        run(&mut empty, 1);
+        assert_eq!(empty.program_counter(), 0x202);
+
+        //a lone 0x6A becomes va := 0
+        let rom = RomBuffer::from_bytes(vec![0x6A]).unwrap();
+        assert_eq!(rom.buffer, vec![0x6A, 0x00]);
+        let mut single = CpuBuilder::new(rom).build();
+        single.set_register(0xA, 9).unwrap();
+        assert!(matches!(
+            single.peek_instruction(),
+            Some(Instruction::LoadRegisterX { x: 0xA, kk: 0 })
+        ));
+        run(&mut single, 1);
+        assert_eq!(single.get_register(0xA).unwrap(), 0);
+    }
+
+    #[test]
+    fn switching_resolution_clears_the_screen() {
+        //a pixel from the sprite at 0x210 drawn at 1,1, high resolution, the pixel again, low
+        //resolution
+        let program = [
+            0x60, 0x01, 0xA2, 0x10, 0xD0, 0x01, 0x00, 0xFF, 0xD0, 0x01, 0x00, 0xFE, 0x12, 0x0C,
+            0x00, 0x00, 0x80, 0x00,
+        ];
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 3);
+        assert!(cpu.framebuffer().is_on(1, 1));
+        run(&mut cpu, 1);
+        assert!(cpu.framebuffer().is_blank());
+        assert_eq!(cpu.display_dimensions(), (128, 64));
+        run(&mut cpu, 1);
+        assert!(cpu.framebuffer().is_on(1, 1));
+        run(&mut cpu, 1);
+        assert!(cpu.framebuffer().is_blank());
+        assert_eq!(cpu.display_dimensions(), (64, 32));
+    }
+
+    #[test]
+    fn dxy0_draws_16_by_16_in_high_resolution() {
+        //the sprite at 0x20E lights the first and last column of every row, drawn at 4,5
+        let mut program = vec![
+            0x00, 0xFF, 0x60, 0x04, 0x61, 0x05, 0xA2, 0x0E, 0xD0, 0x10, 0x12, 0x0A, 0x00, 0x00,
+        ];
+        program.extend([0x80, 0x01].repeat(16));
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 5);
+        let fb = cpu.framebuffer();
+        assert_eq!(fb.pixels().filter(|&pixel| pixel != 0).count(), 32);
+        for (x, y) in [(4, 5), (19, 5), (4, 20), (19, 20)] {
+            assert!(fb.is_on(x, y));
+        }
+        assert!(!fb.is_on(20, 5));
+        assert!(!fb.is_on(4, 21));
+
+        //in low resolution it draws nothing
+        program[1] = 0xE0;
+        let mut cpu = drawing_cpu(&program, false);
+        run(&mut cpu, 5);
+        assert!(cpu.framebuffer().is_blank());
+    }
+
+    #[test]
+    fn unknown_opcode_policies() {
+        //e0ff isn't an instruction, then v1 := 1
+        let program = [0xE0, 0xFF, 0x61, 0x01];
+        let mut halting = cpu(&program);
+        run(&mut halting, 1);
+        assert!(halting.is_halted());
+        assert_eq!(halting.program_counter(), 0x200);
+        let unknown = halting.unknown_opcode().unwrap();
+        assert_eq!((unknown.address, unknown.opcode), (0x200, 0xE0FF));
+
+        for policy in [UnknownOpcodePolicy::Skip, UnknownOpcodePolicy::Noop] {
+            let mut cpu = cpu(&program);
+            cpu.set_unknown_opcode_policy(policy);
+            cpu.set_profiling(true);
+            run(&mut cpu, 2);
+            assert!(!cpu.is_halted());
+            assert!(cpu.unknown_opcode().is_none());
+            assert_eq!(cpu.get_register(1).unwrap(), 1);
+            //only a noop is seen as having run
+            let noops = cpu
+                .profile_report()
+                .iter()
+                .any(|&(kind, _)| kind == InstructionKind::Noop);
+            assert_eq!(noops, policy == UnknownOpcodePolicy::Noop);
+        }
+    }
+
+    #[test]
+    fn key_16_is_ignored() {
+        let mut cpu = cpu(&[0x6F, 0x07]);
+        run(&mut cpu, 1);
+        let before = cpu.snapshot();
+        let ram = cpu.memory_dump();
+        cpu.set_key_state(16, true);
+        assert!(!cpu.key_state(16));
+        assert_eq!(cpu.pressed_keys().count(), 0);
+        assert_eq!(cpu.snapshot(), before);
+        assert!(cpu.memory_dump() == ram, "ram changed");
+    }
+
+    #[test]
+    fn random_programs_never_panic() {
+        let mut rng = StdRng::seed_from_u64(2125);
+        for quirks in [
+            Quirks::chip8(),
+            Quirks::superchip_legacy(),
+            Quirks::xochip(),
+        ] {
+            for _ in 0..1000 {
+                let program: Vec<u8> = (0..256).map(|_| rng.gen()).collect();
+                let mut cpu = cpu_with(&program, quirks);
+                for cycle in 0..2000 {
+                    if cpu.cycle().is_err() {
+                        break;
+                    }
+                    if cycle % 10 == 0 {
+                        cpu.vblank();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,724 +1,1341 @@
-use ::rand::thread_rng;
-use ::rand::Rng;
-
+use audio::{AudioConfig, Beeper};
+use chip8_emulator::{
+    draw_framebuffer, framebuffer_to_rgba, hex_dump, render_ascii, until, Chip8Variant, Cpu,
+    CpuBuilder, CpuError, Framebuffer, FramebufferSink, OutOfBounds, Quirk, Quirks, RegionKind,
+    RomBuffer, RomError, UnknownOpcodePolicy, DISPLAY_HEIGHT, DISPLAY_WIDTH, NUM_KEYS,
+    NUM_REGISTERS, RAM_SIZE, REFRESH_RATE, ROM_START_ADDRESS,
+};
 use macroquad::prelude::*;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::Duration;
 
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
-const RAM_SIZE: usize = 4096; //in bytes :)
-const CYCLES_PER_FRAME: usize = 5;
-
-///The ram of the chip8 cpu, uses big endian, and is laid out in the following way:
-///0x000 start of chip-8 ram
-///0x000 to 0x080 reserved for fontset
-///0x200 start of most chip-8 programs
-///0x600 start of eti 660 chip8 programs
-///0xfff end of chip8 ram
-#[derive(Debug, Copy, Clone)]
-struct RAM {
-    bytes: [u8; RAM_SIZE],
-}
-impl RAM {
-    fn with_fonts() -> Self {
-        let mut ram = Self {
-            bytes: [0; RAM_SIZE],
-        };
+mod audio;
 
-        let fontset = vec![
-            0xF0, 0x90, 0x90, 0x90, 0xF0, //0
-            0x20, 0x60, 0x20, 0x20, 0x70, //1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, //2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, //3
-            0x90, 0x90, 0xF0, 0x10, 0x10, //4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, //5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, //6
-            0xF0, 0x10, 0x20, 0x40, 0x40, //7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, //8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, //9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, //a
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, //b
-            0xF0, 0x80, 0x80, 0x80, 0xF0, //c
-            0xE0, 0x90, 0x90, 0x90, 0xE0, //d
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, //e
-            0xF0, 0x80, 0xF0, 0x80, 0x80, //f
-        ];
+///The keyboard keys standing in for the keypad, indexed by key value. Laid out the same way as
+///the keypad, it covers 1-4, Q-R, A-F and Z-V on a qwerty keyboard.
+const KEYMAP: [KeyCode; NUM_KEYS] = [
+    KeyCode::X,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Q,
+    KeyCode::W,
+    KeyCode::E,
+    KeyCode::A,
+    KeyCode::S,
+    KeyCode::D,
+    KeyCode::Z,
+    KeyCode::C,
+    KeyCode::Key4,
+    KeyCode::R,
+    KeyCode::F,
+    KeyCode::V,
+];
+///the keys that toggle the quirks while control is held, in the order of Quirk::ALL
+const QUIRK_KEYS: [KeyCode; 8] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+];
+///file extensions accepted when a file is dropped onto the window
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "8o", "rom"];
+///rgba colors of lit and unlit pixels
+const FOREGROUND: [u8; 4] = [0, 0, 0, 255];
+const BACKGROUND: [u8; 4] = [255, 255, 255, 255];
+///how many png pixels a single chip8 pixel covers in a screenshot
+const SCREENSHOT_SCALE: usize = 8;
+///how many gif pixels a single chip8 pixel covers in a recording
+const RECORDING_SCALE: usize = 4;
+///recordings are saved automatically once they hold this many frames, a minute at 60fps
+const MAX_RECORDED_FRAMES: usize = 60 * 60;
+///fraction of its brightness an unlit pixel keeps each frame when fading is on
+const FADE_PER_FRAME: f32 = 0.6;
+///how much faster the emulator runs while tab is held
+const TURBO_SPEED: f32 = 10.0;
+///refresh rate of the european machines some roms were written on
+const PAL_REFRESH_RATE: u32 = 50;
+///how much the volume keys change the volume of the beep
+const VOLUME_STEP: f32 = 0.05;
+///most instructions run at once by the run until keys, so a condition that never holds can't hang
+const RUN_UNTIL_LIMIT: usize = 1_000_000;
+///how many instructions can be stepped back while paused. Only the instructions that write ram
+///or draw keep a copy of it, so this stays small enough to be on while the rom runs.
+const HISTORY_LENGTH: usize = 1000;
+///color of the lines between pixels when the grid is shown, faint so sprites stay readable
+const GRID_COLOR: Color = Color::new(0.5, 0.5, 0.5, 0.35);
+///how long a frame takes at the least while the emulator isn't running, about 20 frames a second
+#[cfg(not(target_arch = "wasm32"))]
+const IDLE_FRAME_TIME: Duration = Duration::from_millis(50);
 
-        for (idx, value) in ram.bytes[0..fontset.len()].iter_mut().enumerate() {
-            *value = fontset[idx];
+///Phosphor style afterglow: pixels that turn off fade out over a few frames instead of
+///disappearing at once, which hides most of the flicker caused by xor drawing
+struct Fade {
+    ///row by row, a display of the given width
+    brightness: Vec<f32>,
+    width: usize,
+}
+impl Fade {
+    fn new((width, height): (usize, usize)) -> Self {
+        Fade {
+            brightness: vec![0.0; width * height],
+            width,
         }
-        println!("mom-oh-ree: {:?}", &ram);
-        ram
     }
 
-    ///returns a value from ram
-    fn get(self, index: u16) -> u16 {
-        ((self.bytes[index as usize] as u16) << 8) | self.bytes[(index + 1) as usize] as u16
+    fn update(&mut self, fb: &Framebuffer) {
+        draw_framebuffer(fb, self);
     }
-}
 
-struct RomBuffer {
-    buffer: Vec<u8>,
-}
-impl RomBuffer {
-    fn new(file: &str) -> Self {
-        let buffer: Vec<u8> = std::fs::read(file).unwrap();
-        RomBuffer { buffer: buffer }
+    ///like framebuffer_to_rgba, but blends between the colors by each pixel's brightness
+    fn to_rgba(&self, fg: [u8; 4], bg: [u8; 4]) -> Vec<u8> {
+        self.brightness
+            .iter()
+            .flat_map(|&brightness| {
+                let mut color = [0; 4];
+                for (channel, value) in color.iter_mut().enumerate() {
+                    let (fg, bg) = (fg[channel] as f32, bg[channel] as f32);
+                    *value = (bg + (fg - bg) * brightness) as u8;
+                }
+                color
+            })
+            .collect()
     }
 }
-#[derive(Clone, Copy)]
-///# All 16 8 bit registers, and the 16 bit I register
-struct Registers {
-    register: [u8; 16],
-    vindex: u16,
-    delay_timer: u8,
-    sound_timer: u8,
+impl FramebufferSink for Fade {
+    ///lit pixels go to full brightness, all others decay a little
+    fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let brightness = &mut self.brightness[y * self.width + x];
+        *brightness = if on {
+            1.0
+        } else {
+            *brightness * FADE_PER_FRAME
+        };
+    }
 }
 
-impl Registers {
-    fn new() -> Self {
-        Registers {
-            register: [0u8; 16],
-            vindex: 0,
-            delay_timer: 0,
-            sound_timer: 0,
+///Measures the speed the cpu actually runs at, averaged over a second
+struct SpeedMeter {
+    started: f64,
+    cycles: u64,
+    instructions_per_second: u64,
+}
+impl SpeedMeter {
+    fn new(c: &Cpu) -> Self {
+        SpeedMeter {
+            started: get_time(),
+            cycles: c.cycle_count(),
+            instructions_per_second: 0,
         }
     }
-    fn set_index_register(&mut self, value: u16) {
-        self.vindex = value;
-    }
-    fn get_index_register(&self) -> u16 {
-        self.vindex
+
+    fn update(&mut self, c: &Cpu) {
+        let elapsed = get_time() - self.started;
+        if elapsed >= 1.0 {
+            //the count starts over when a rom is loaded
+            let cycles = c.cycle_count().saturating_sub(self.cycles);
+            self.instructions_per_second = (cycles as f64 / elapsed) as u64;
+            self.started = get_time();
+            self.cycles = c.cycle_count();
+        }
     }
-    fn set_sound_timer(&mut self, value: u8) {
-        self.sound_timer = value;
+}
+
+///The display as it's drawn on the window. With flicker reduction a pixel stays lit when it was
+///lit in the previous frame, so sprites that get erased and redrawn don't blink. That isn't how
+///the original machines looked, moving sprites leave a short trail instead.
+fn shown_framebuffer(c: &Cpu, reduce_flicker: bool) -> Framebuffer {
+    let mut fb = c.framebuffer().clone();
+    let previous = c.previous_framebuffer();
+    //right after the resolution changed the previous frame doesn't line up with this one
+    if !reduce_flicker || previous.width() != fb.width() {
+        return fb;
     }
-    fn set_delay_timer(&mut self, value: u8) {
-        self.delay_timer = value;
+    for y in 0..fb.height() {
+        for x in 0..fb.width() {
+            let planes = previous.get(x, y);
+            for plane in (0..fb.planes()).filter(|plane| planes >> plane & 1 == 1) {
+                fb.set(x, y, plane, true);
+            }
+        }
     }
-    fn get_delay_timer(&self) -> u8 {
-        return self.delay_timer;
+    fb
+}
+
+///how a toggled setting is described in messages
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
     }
-    fn decrement_sound_timer(&mut self) {
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+}
+
+///whether either control key is held
+fn is_control_down() -> bool {
+    is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl)
+}
+
+///whether either shift key is held
+fn is_shift_down() -> bool {
+    is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift)
+}
+
+///Saves the framebuffer as a png scaled up by SCREENSHOT_SCALE, returns the file name. High
+///resolution is scaled up half as much, so screenshots are always the same size.
+fn save_screenshot(fb: &Framebuffer) -> Result<String, image::ImageError> {
+    let scale = SCREENSHOT_SCALE * DISPLAY_WIDTH / fb.width();
+    let pixels = framebuffer_to_rgba(fb, FOREGROUND, BACKGROUND);
+    let mut bytes = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks(fb.width() * 4) {
+        let scaled_row: Vec<u8> = row
+            .chunks(4)
+            .flat_map(|pixel| pixel.repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            bytes.extend_from_slice(&scaled_row);
         }
     }
-    fn decrement_delay_timer(&mut self) {
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+
+    let name = timestamped_file_name("png");
+    image::save_buffer(
+        &name,
+        &bytes,
+        (fb.width() * scale) as u32,
+        (fb.height() * scale) as u32,
+        image::ColorType::Rgba8,
+    )?;
+    Ok(name)
+}
+
+///Saves recorded frames as an animated gif scaled up by RECORDING_SCALE, returns the file name.
+///Like screenshots, frames in high resolution are scaled up half as much.
+fn save_recording(frames: &[Framebuffer]) -> Result<String, gif::EncodingError> {
+    let width = (DISPLAY_WIDTH * RECORDING_SCALE) as u16;
+    let height = (DISPLAY_HEIGHT * RECORDING_SCALE) as u16;
+    //palette index 0 is the background, 1 the foreground
+    let palette = [&BACKGROUND[..3], &FOREGROUND[..3]].concat();
+
+    let name = timestamped_file_name("gif");
+    let mut encoder = gif::Encoder::new(std::fs::File::create(&name)?, width, height, &palette)?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    for (index, fb) in frames.iter().enumerate() {
+        let scale = RECORDING_SCALE * DISPLAY_WIDTH / fb.width();
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..fb.height() {
+            let scaled_row: Vec<u8> = (0..fb.width())
+                .flat_map(|x| std::iter::repeat_n(fb.is_on(x, y) as u8, scale))
+                .collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&scaled_row);
+            }
         }
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+        //gif delays are in hundredths of a second, so 60fps is approximated by
+        //alternating delays that add up to the exact time at every frame
+        frame.delay = ((index + 1) * 100 / 60 - index * 100 / 60) as u16;
+        encoder.write_frame(&frame)?;
     }
+    Ok(name)
+}
 
-    fn get_register(&self, register: u8) -> u8 {
-        return self.register[register as usize];
+///Encodes a finished recording on a background thread, so the emulator doesn't stutter
+fn finish_recording(frames: Vec<Framebuffer>) {
+    run_in_background(move || match save_recording(&frames) {
+        Ok(name) => println!("saved recording to {}", name),
+        Err(error) => eprintln!("could not save recording: {}", error),
+    });
+}
+
+///Runs the work on a thread of its own. The web has no threads, so there it runs right away
+fn run_in_background(work: impl FnOnce() + Send + 'static) {
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::spawn(work);
+    #[cfg(target_arch = "wasm32")]
+    work();
+}
+
+///Builds a file name in the working directory that won't clash with earlier captures
+fn timestamped_file_name(extension: &str) -> String {
+    //miniquad's clock also works on the web, where SystemTime panics
+    let seconds = miniquad::date::now() as u64;
+    format!("chippie-{}.{}", seconds, extension)
+}
+
+///Saves a copy of ram as a binary file and as a hex dump next to it, returns the name of the
+///binary file
+fn save_memory_dump(memory: &[u8]) -> std::io::Result<String> {
+    let name = timestamped_file_name("bin");
+    std::fs::write(&name, memory)?;
+    std::fs::write(name.replace(".bin", ".txt"), hex_dump(memory))?;
+    Ok(name)
+}
+
+///Saves the rom as a `.ch8` file, which turns an assembled `.8o` program into a plain rom,
+///returns the file name
+fn save_rom(rom: &RomBuffer) -> std::io::Result<String> {
+    let name = timestamped_file_name("ch8");
+    rom.save(&name)?;
+    Ok(name)
+}
+
+///Saves a listing of the rom as a `.asm` text file, returns the file name
+fn save_disassembly(rom: &RomBuffer, start_address: usize) -> std::io::Result<String> {
+    let name = timestamped_file_name("asm");
+    std::fs::write(&name, rom.disassemble(start_address))?;
+    Ok(name)
+}
+
+///Where on the window the display is drawn and how large. Stretched it fills the window, integer
+///scaled it gets the largest whole number scale that fits and is centered.
+fn display_area(integer_scale: bool, (width, height): (usize, usize)) -> (Vec2, Vec2) {
+    let window = vec2(screen_width(), screen_height());
+    if !integer_scale {
+        return (Vec2::ZERO, window);
+    }
+    let scale = (window.x / width as f32)
+        .min(window.y / height as f32)
+        .floor()
+        .max(1.0);
+    let size = vec2(width as f32, height as f32) * scale;
+    (((window - size) / 2.0).floor(), size)
+}
+
+///Draws ram as a bar along the bottom of the window, colored by what each region holds, with
+///the program counter marked and a legend above it
+fn draw_memory_map(c: &Cpu) {
+    let (x, width, height) = (10.0, screen_width() - 20.0, 16.0);
+    let y = screen_height() - 60.0;
+    let scale = width / RAM_SIZE as f32;
+
+    let mut legend_x = x;
+    for kind in [
+        RegionKind::Font,
+        RegionKind::LargeFont,
+        RegionKind::Program,
+        RegionKind::Unused,
+    ] {
+        let label = kind.to_string();
+        draw_text(&label, legend_x, y - 6.0, 20.0, region_color(kind));
+        legend_x += measure_text(&label, None, 20, 1.0).width + 20.0;
     }
-    fn set_register(&mut self, register: u8, value: u8) {
-        self.register[register as usize] = value;
+    for (start, end, kind) in c.memory_regions() {
+        let region_width = (end - start) as f32 * scale;
+        draw_rectangle(
+            x + start as f32 * scale,
+            y,
+            region_width,
+            height,
+            region_color(kind),
+        );
     }
+    let pc_x = x + c.program_counter() as f32 * scale;
+    draw_line(pc_x, y - 2.0, pc_x, y + height + 2.0, 2.0, RED);
 }
 
-#[derive(Clone, Copy)]
-struct Stack {
-    values: [u16; 16],
+///Draws thin lines along the edges of every chip8 pixel of the display, which helps lining up
+///sprites while making a rom
+fn draw_grid(position: Vec2, size: Vec2, (width, height): (usize, usize)) {
+    let cell = vec2(size.x / width as f32, size.y / height as f32);
+    for column in 0..=width {
+        let x = position.x + column as f32 * cell.x;
+        draw_line(x, position.y, x, position.y + size.y, 1.0, GRID_COLOR);
+    }
+    for row in 0..=height {
+        let y = position.y + row as f32 * cell.y;
+        draw_line(position.x, y, position.x + size.x, y, 1.0, GRID_COLOR);
+    }
 }
-impl Stack {
-    fn new() -> Self {
-        Stack { values: [0; 16] }
+
+///The color of a region in the memory map
+fn region_color(kind: RegionKind) -> Color {
+    match kind {
+        RegionKind::Font => ORANGE,
+        RegionKind::LargeFont => GOLD,
+        RegionKind::Program => SKYBLUE,
+        RegionKind::Unused => LIGHTGRAY,
     }
 }
 
-struct CPU {
-    display: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-    ///Program counter, used to keep track of what to fetch,decode and execute from ram, initialized at 0x200
-    program_counter: u16,
-    memory: RAM,
-    registers: Registers,
-    stack: Stack, //stack for keeping track of where to return to after subroutine, can go into 16 nested subroutines before stackoverflow
-    stackpointer: u8, //only contains indexes to locations in the stack, so 0 through 15
+///The file name of a rom, the last part of the url for downloaded ones
+fn rom_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
 }
 
-impl CPU {
-    fn fetch(&self, ram: &RAM) -> u16 {
-        ram.get(self.program_counter)
+///A rom that is still being loaded, the result arrives once the file has been read
+type PendingRom = Receiver<Result<RomBuffer, RomError>>;
+
+///Builds a rom from a file dropped onto the window, files without a rom extension are refused.
+///Reading the file happens on a background thread, so a slow disk doesn't freeze the window.
+fn load_dropped_file(file: DroppedFile) -> PendingRom {
+    let (sender, receiver) = channel();
+    let path = file.path.unwrap_or_default();
+    let is_rom = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ROM_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+
+    //the send only fails when a newer rom replaced this one, so its result isn't needed anymore
+    if !is_rom {
+        let _ = sender.send(Err(RomError::NotARom(path.display().to_string())));
+    } else if let Some(bytes) = file.bytes {
+        let _ = sender.send(rom_from_file_contents(&path, bytes));
+    } else {
+        //not every platform hands us the contents, fall back to reading the path
+        run_in_background(move || {
+            let rom = std::fs::read(&path)
+                .map_err(RomError::from)
+                .and_then(|bytes| rom_from_file_contents(&path, bytes));
+            let _ = sender.send(rom);
+        });
     }
+    receiver
+}
 
-    fn decode(&self, opcode: u16) -> Instruction {
-        match self.first_nibble(opcode) {
-            0x0 => match self.last_byte(opcode) {
-                0xE0 => Instruction::ClearScreen,
-                0xEE => Instruction::ReturnFromSubroutine,
-                _ => Instruction::NOOP, //panic!("Unimplemented opcode: {:#04x}", opcode),
-            },
-            0x1 => Instruction::JUMP {
-                nnn: self.oxxx(opcode),
-            },
-            0x2 => Instruction::CallSubroutineAtNNN {
-                nnn: self.oxxx(opcode),
-            },
-            0x3 => Instruction::SkipNextInstructionIfXIsKK {
-                x: self.second_nibble(opcode),
-                kk: self.last_byte(opcode),
-            },
-            0x4 => Instruction::SkipNextInstructionIfXIsNotKK {
-                x: self.second_nibble(opcode),
-                kk: self.last_byte(opcode),
-            },
-            0x5 => Instruction::SkipNextInstructionIfXIsY {
-                x: self.second_nibble(opcode),
-                y: self.third_nibble(opcode),
-            },
-            0x6 => Instruction::LoadRegisterX {
-                x: self.second_nibble(opcode),
-                kk: self.last_byte(opcode),
-            },
-            0x7 => Instruction::AddToRegisterX {
-                x: self.second_nibble(opcode),
-                kk: self.last_byte(opcode),
-            },
-            0x8 => match self.fourth_nibble(opcode) {
-                0x0 => Instruction::LoadRegisterXIntoY {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-                0x1 => Instruction::LoadXOrYinX {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-                0x2 => Instruction::LoadXAndYInX {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-                0x3 => Instruction::LoadXXorYInX {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-
-                0x4 => Instruction::AddYToX {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-                0x5 => Instruction::SubYFromX {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-                0x6 => Instruction::ShiftXRight1 {
-                    x: self.second_nibble(opcode),
-                },
-                0x7 => Instruction::SubXFromY {
-                    x: self.second_nibble(opcode),
-                    y: self.third_nibble(opcode),
-                },
-
-                0xE => Instruction::ShiftXLeft1 {
-                    x: self.second_nibble(opcode),
-                },
-                _ => {
-                    panic!("some other 8xxx thingy")
+///Builds a rom from the contents of a file, `.8o` files hold octo source that gets assembled
+fn rom_from_file_contents(path: &std::path::Path, bytes: Vec<u8>) -> Result<RomBuffer, RomError> {
+    let is_octo = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("8o"));
+    if !is_octo {
+        return RomBuffer::from_bytes(bytes);
+    }
+    match String::from_utf8(bytes) {
+        Ok(source) => RomBuffer::from_octo(&source),
+        Err(_) => Err(RomError::NotARom(path.display().to_string())),
+    }
+}
+
+///Downloads a rom on a background thread. Only as many bytes as fit in memory are read, so a
+///wrong url can't fill up the memory of the emulator.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_url(url: String) -> PendingRom {
+    let (sender, receiver) = channel();
+    run_in_background(move || {
+        let _ = sender.send(download_rom(&url));
+    });
+    receiver
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn download_rom(url: &str) -> Result<RomBuffer, RomError> {
+    use std::io::Read;
+
+    let limit = RAM_SIZE - ROM_START_ADDRESS;
+    let response = ureq::get(url).call().map_err(std::io::Error::other)?;
+    let size = response
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok());
+    if let Some(size) = size.filter(|&size| size > limit) {
+        return Err(RomError::TooLarge(size));
+    }
+
+    //one byte more than fits, so a rom without a length can still be told apart from one that fits
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    rom_from_file_contents(std::path::Path::new(url), bytes)
+}
+
+///Whether a rom argument should be downloaded instead of read from disk
+#[cfg(not(target_arch = "wasm32"))]
+fn is_url(rom: &str) -> bool {
+    rom.starts_with("http://") || rom.starts_with("https://")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const USAGE: &str = "usage: chippie <rom file or url> [--ips <instructions per second>] [--quirks <chip8|superchip|superchip-legacy|xochip>] [--trace <file>] [--seed <number>] [--start <hex address>] [--hz <refresh rate>] [--headless <instructions>] [--dump] [--paused] [--profile] [--watch <hex address>]... [--unknown-opcodes <halt|skip|noop>]";
+
+///Settings picked on the command line
+struct Options {
+    ///path or url of the rom
+    rom: PathBuf,
+    ///speed of the cpu, guessed from the rom when not given
+    instructions_per_second: Option<u32>,
+    ///quirks preset, guessed from the rom when not given
+    quirks: Option<Quirks>,
+    ///file to write the execution trace to
+    trace: Option<PathBuf>,
+    ///seed for the random number generator, for reproducible runs
+    seed: Option<u64>,
+    ///where the rom is loaded and starts running, ROM_START_ADDRESS when not given
+    start_address: Option<usize>,
+    ///how many times per second the timers tick, REFRESH_RATE when not given
+    refresh_rate: Option<u32>,
+    ///run this many instructions without opening a window
+    headless: Option<u64>,
+    ///print the screen as text once the emulator stops
+    dump: bool,
+    ///hold the rom before its first instruction, so it can be stepped through from the start
+    paused: bool,
+    ///count the instructions by kind and print the tally once the emulator stops
+    profile: bool,
+    ///addresses that pause the emulator when the program writes them
+    watchpoints: Vec<u16>,
+    ///what happens to opcodes that aren't instructions, halting on them by default
+    unknown_opcodes: UnknownOpcodePolicy,
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl Options {
+    ///parses the arguments following the program name, returns a message for the user on bad input
+    fn parse(mut args: impl Iterator<Item = OsString>) -> Result<Self, String> {
+        let mut rom = None;
+        let mut instructions_per_second = None;
+        let mut quirks = None;
+        let mut trace = None;
+        let mut seed = None;
+        let mut start_address = None;
+        let mut refresh_rate = None;
+        let mut headless = None;
+        let mut dump = false;
+        let mut paused = false;
+        let mut profile = false;
+        let mut watchpoints = Vec::new();
+        let mut unknown_opcodes = UnknownOpcodePolicy::default();
+
+        while let Some(arg) = args.next() {
+            //paths don't have to be valid utf-8, everything else does
+            match arg.to_str().unwrap_or_default() {
+                "--ips" => {
+                    let value = next_value(&mut args, "--ips needs a value")?;
+                    instructions_per_second =
+                        Some(value.parse().map_err(|_| {
+                            format!("{} is not a valid number of instructions", value)
+                        })?);
                 }
-            },
-            0x9 => Instruction::SkipNextInstructionIfXIsNotY {
-                x: self.second_nibble(opcode),
-                y: self.third_nibble(opcode),
-            },
-            0xA => Instruction::SetIndexRegister {
-                nnn: self.oxxx(opcode),
-            },
-            0xB => Instruction::JumpToAddressPlusV0 {
-                nnn: self.oxxx(opcode),
-            },
-            0xC => Instruction::SetXToRandom {
-                x: self.second_nibble(opcode),
-                kk: self.last_byte(opcode),
-            },
-            0xD => Instruction::DISPLAY {
-                x: self.second_nibble(opcode),
-                y: self.third_nibble(opcode),
-                n: self.fourth_nibble(opcode),
-            },
-            0xE => match self.last_byte(opcode) {
-                0xA1 => Instruction::SkipIfVxNotPressed {
-                    x: self.second_nibble(opcode),
-                },
-                0x9E => Instruction::SkipIfVxPressed {
-                    x: self.second_nibble(opcode),
-                },
-                _ => {
-                    panic!("unimplemented opcode: 0x{:04x}", opcode);
+                "--quirks" => {
+                    let value = next_value(&mut args, "--quirks needs a value")?;
+                    quirks = Some(
+                        Quirks::from_name(&value)
+                            .ok_or_else(|| format!("unknown quirks preset {}", value))?,
+                    );
                 }
-            },
-            0xF => match self.last_byte(opcode) {
-                0x0A => Instruction::WaitForKeyPressed {
-                    x: self.second_nibble(opcode),
-                },
-                0x07 => Instruction::SetXToDelayTimer {
-                    x: self.second_nibble(opcode),
-                },
-                0x15 => Instruction::SetDelayTimerToX {
-                    x: self.second_nibble(opcode),
-                },
-                0x18 => Instruction::SetSoundTimerToX {
-                    x: self.second_nibble(opcode),
-                },
-                0x1E => Instruction::AddXtoI {
-                    x: self.second_nibble(opcode),
-                },
-                0x29 => Instruction::SetIToSpriteX {
-                    x: self.second_nibble(opcode),
-                },
-                0x33 => Instruction::LoadBCDOfX {
-                    x: self.second_nibble(opcode),
-                },
-                0x55 => Instruction::Write0ThroughX {
-                    x: self.second_nibble(opcode),
-                },
-                0x65 => Instruction::Load0ThroughX {
-                    x: self.second_nibble(opcode),
-                },
-                _ => {
-                    panic!("unimplemented opcode: 0x{:06x}", opcode);
+                "--trace" => {
+                    trace = Some(PathBuf::from(args.next().ok_or("--trace needs a file")?))
                 }
-            },
-            _ => {
-                panic!("cannot decode,opcode not implemented: 0x{:04x}", opcode)
+                "--seed" => {
+                    let value = next_value(&mut args, "--seed needs a value")?;
+                    seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("{} is not a valid seed", value))?,
+                    );
+                }
+                "--start" => {
+                    let value = next_value(&mut args, "--start needs an address")?;
+                    start_address = Some(
+                        usize::from_str_radix(value.trim_start_matches("0x"), 16)
+                            .map_err(|_| format!("{} is not a valid address", value))?,
+                    );
+                }
+                "--hz" => {
+                    let value = next_value(&mut args, "--hz needs a value")?;
+                    refresh_rate = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|&rate| rate > 0)
+                            .ok_or_else(|| format!("{} is not a valid refresh rate", value))?,
+                    );
+                }
+                "--headless" => {
+                    let value = next_value(&mut args, "--headless needs a value")?;
+                    headless =
+                        Some(value.parse().map_err(|_| {
+                            format!("{} is not a valid number of instructions", value)
+                        })?);
+                }
+                "--dump" => dump = true,
+                "--paused" => paused = true,
+                "--profile" => profile = true,
+                "--watch" => {
+                    let value = next_value(&mut args, "--watch needs an address")?;
+                    watchpoints.push(
+                        u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                            .map_err(|_| format!("{} is not a valid address", value))?,
+                    );
+                }
+                "--unknown-opcodes" => {
+                    let value = next_value(&mut args, "--unknown-opcodes needs a value")?;
+                    unknown_opcodes = UnknownOpcodePolicy::from_name(&value)
+                        .ok_or_else(|| format!("unknown opcode policy {}", value))?;
+                }
+                "-h" | "--help" => return Err(String::from("chip8 interpreter")),
+                option if option.starts_with('-') => {
+                    return Err(format!("unknown option {}", option))
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument {}", arg.to_string_lossy())),
             }
         }
+
+        Ok(Options {
+            rom: rom.ok_or("no rom given")?,
+            instructions_per_second,
+            quirks,
+            trace,
+            seed,
+            start_address,
+            refresh_rate,
+            headless,
+            dump,
+            paused,
+            profile,
+            watchpoints,
+            unknown_opcodes,
+        })
     }
-    fn u8_to_keycode(code: u8) -> macroquad::input::KeyCode {
-        match code {
-            0x0 => macroquad::input::KeyCode::X,
-            0x1 => macroquad::input::KeyCode::Key1,
-            0x2 => macroquad::input::KeyCode::Key2,
-            0x3 => macroquad::input::KeyCode::Key3,
-            0x4 => macroquad::input::KeyCode::Q,
-            0x5 => macroquad::input::KeyCode::W,
-            0x6 => macroquad::input::KeyCode::E,
-            0x7 => macroquad::input::KeyCode::A,
-            0x8 => macroquad::input::KeyCode::S,
-            0x9 => macroquad::input::KeyCode::D,
-            0xa => macroquad::input::KeyCode::Z,
-            0xb => macroquad::input::KeyCode::C,
-            0xc => macroquad::input::KeyCode::Key3,
-            0xd => macroquad::input::KeyCode::R,
-            0xe => macroquad::input::KeyCode::F,
-            0xf => macroquad::input::KeyCode::V,
-            _ => {
-                panic!("Invalid keycode");
+}
+
+///The argument following an option, which has to be valid utf-8
+#[cfg(not(target_arch = "wasm32"))]
+fn next_value(args: &mut impl Iterator<Item = OsString>, missing: &str) -> Result<String, String> {
+    let value = args.next().ok_or(missing)?;
+    value
+        .into_string()
+        .map_err(|value| format!("{} is not valid text", value.to_string_lossy()))
+}
+
+///A cpu set up the way the command line asked, quirks and speed that weren't picked match the
+///dialect the rom looks like. Fails when the rom doesn't fit at the start address.
+fn cpu_builder(rom: RomBuffer, options: &Options) -> Result<CpuBuilder, OutOfBounds> {
+    let variant = rom.detect_format();
+    //the guess is a heuristic, so say when it changes anything
+    if variant != Chip8Variant::Chip8
+        && (options.quirks.is_none() || options.instructions_per_second.is_none())
+    {
+        eprintln!(
+            "the rom looks like {:?}, running it with the quirks and speed of that unless \
+             --quirks or --ips say otherwise",
+            variant
+        );
+    }
+    let mut builder = CpuBuilder::new(rom)
+        .quirks(
+            options
+                .quirks
+                .unwrap_or_else(|| Quirks::for_variant(variant)),
+        )
+        .instructions_per_second(
+            options
+                .instructions_per_second
+                .unwrap_or_else(|| variant.instructions_per_second()),
+        )
+        .unknown_opcode_policy(options.unknown_opcodes);
+    if let Some(seed) = options.seed {
+        builder = builder.seed(seed);
+    }
+    if let Some(refresh_rate) = options.refresh_rate {
+        builder = builder.refresh_rate(refresh_rate);
+    }
+    if let Some(address) = options.start_address {
+        builder = builder.start_address(address)?;
+    }
+    Ok(builder)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    let options = match Options::parse(std::env::args_os().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+    //creating a chip8 cpu object with a rom loaded
+    let url = options.rom.to_str().filter(|rom| is_url(rom));
+    let rom = if let Some(url) = url {
+        load_url(url.to_string())
+            .recv()
+            .expect("the download always sends its result")
+    } else {
+        RomBuffer::new(&options.rom)
+    };
+    let rom = match rom {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}: {}", options.rom.display(), error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut builder = match cpu_builder(rom.clone(), &options) {
+        Ok(builder) => builder,
+        Err(out_of_bounds) => {
+            eprintln!(
+                "{}: rom does not fit, {}",
+                options.rom.display(),
+                out_of_bounds
+            );
+            std::process::exit(1);
+        }
+    };
+    if let Some(path) = &options.trace {
+        match std::fs::File::create(path) {
+            Ok(file) => builder = builder.trace(Box::new(std::io::BufWriter::new(file))),
+            Err(error) => {
+                eprintln!("{}: {}", path.display(), error);
+                std::process::exit(1);
             }
         }
     }
+    let mut c = builder.build();
+    c.set_profiling(options.profile);
+    for &address in &options.watchpoints {
+        c.add_watchpoint(address);
+    }
 
-    ///Execute the instruction, for details on the instruction, check the instruction enum
-    ///definition
-    fn execute(&mut self, instruction: Instruction) {
-        match instruction {
-            Instruction::NOOP => {
-                //do nothing...
-            }
-            //00E0
-            Instruction::ClearScreen => {
-                self.display
-                    .iter_mut()
-                    .for_each(|x| *x = [false; DISPLAY_WIDTH]);
-            }
-            //00EE
-            Instruction::ReturnFromSubroutine => {
-                self.stackpointer -= 1;
-                self.program_counter = self.stack.values[self.stackpointer as usize];
-            }
-            //1NNN
-            Instruction::JUMP { nnn } => {
-                println!("Jumping to {:?}",nnn);
-                self.program_counter = nnn;
-            }
-            //2NNN
-            Instruction::CallSubroutineAtNNN { nnn } => {
-                self.stack.values[self.stackpointer as usize] = self.program_counter;
-                self.stackpointer += 1;
-                self.program_counter = nnn;
-            }
-            //3XKK
-            Instruction::SkipNextInstructionIfXIsKK { x, kk } => {
-                let vx = self.registers.get_register(x);
-                if vx == kk {
-                    self.program_counter += 2;
-                }
-            }
-            //4XKK
-            Instruction::SkipNextInstructionIfXIsNotKK { x, kk } => {
-                let vx = self.registers.get_register(x);
+    if let Some(instructions) = options.headless {
+        run_headless(c, &options, instructions);
+        return;
+    }
+    //macroquad can't change the title later on, so it keeps the name of the first rom, see run
+    macroquad::Window::new(
+        &format!(
+            "Chip 8 interpreter \"Chippie\" - {}",
+            rom_name(&options.rom)
+        ),
+        run(c, rom, options),
+    );
+}
 
-                if vx != kk {
-                    self.program_counter += 2;
-                }
-            }
-            //5XY0
-            Instruction::SkipNextInstructionIfXIsY { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
+///There's no command line on the web, so it starts out empty and roms are dropped onto the page
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    let options = Options {
+        rom: PathBuf::new(),
+        instructions_per_second: None,
+        quirks: None,
+        trace: None,
+        seed: Some((miniquad::date::now() * 1000.0) as u64),
+        start_address: None,
+        refresh_rate: None,
+        headless: None,
+        dump: false,
+        paused: false,
+        profile: false,
+        watchpoints: Vec::new(),
+        unknown_opcodes: UnknownOpcodePolicy::default(),
+    };
+    let rom = RomBuffer::from_bytes(Vec::new()).expect("an empty rom always fits");
+    let c = cpu_builder(rom.clone(), &options)
+        .expect("an empty rom always fits")
+        .build();
+    macroquad::Window::new("Chip 8 interpreter \"Chippie\"", run(c, rom, options));
+}
+
+///Runs the rom without a window a frame at a time, until the frame the instructions ran out in
+#[cfg(not(target_arch = "wasm32"))]
+fn run_headless(mut c: Cpu, options: &Options, instructions: u64) {
+    let mut error = None;
+    while c.cycle_count() < instructions && !c.is_halted() && error.is_none() {
+        error = c.step_for(c.frame_duration()).err();
+        //there's no one to pause for, so every hit is only reported
+        if let Some(hit) = c.watchpoint_hit() {
+            println!("watchpoint: {}", hit);
+        }
+    }
+    if let Some(unknown) = c.unknown_opcode() {
+        eprintln!("program stopped: {}", unknown);
+    }
+
+    if options.dump {
+        print!("{}", render_ascii(c.framebuffer()));
+    }
+    if options.profile {
+        print_profile(&c);
+    }
+    if let Some(stopped) = error {
+        eprintln!("program stopped: {}", stopped);
+        std::process::exit(1);
+    }
+}
 
-                if vx == vy {
-                    self.program_counter += 2;
+async fn run(mut c: Cpu, mut current_rom: RomBuffer, options: Options) {
+    let mut rom_info = current_rom.info();
+    //used for
+    let mut image = Image::gen_image_color(DISPLAY_WIDTH as u16, DISPLAY_HEIGHT as u16, WHITE);
+    let mut texture = Texture2D::from_image(&image);
+    texture.set_filter(FilterMode::Nearest);
+    let mut running = true;
+    //frames captured since recording was toggled on, None when not recording
+    let mut recording: Option<Vec<Framebuffer>> = None;
+    //the error that stopped the program, execution stays stopped until another rom is loaded
+    let mut error: Option<CpuError> = None;
+    //a dropped rom that is still being read, and the name it goes by
+    let mut pending_rom: Option<PendingRom> = None;
+    let mut pending_name = String::new();
+    //the window title can't change, so the name of the rom that's running is drawn when it isn't
+    //the one in the title anymore
+    let title_name = rom_name(&options.rom);
+    let mut current_name = title_name.clone();
+    //why the last dropped file couldn't be loaded, shown until another file is dropped
+    let mut load_error: Option<RomError> = None;
+    //pixel brightnesses when fading is turned on
+    let mut fade: Option<Fade> = None;
+    //whether pixels lit in the previous frame are shown as well, see shown_framebuffer
+    let mut reduce_flicker = false;
+    //measured speed when the speed overlay is turned on
+    let mut speed_meter: Option<SpeedMeter> = None;
+    //whether every chip8 pixel covers a whole number of screen pixels instead of stretching
+    let mut integer_scale = false;
+    //whether the facts about the rom are shown
+    let mut show_rom_info = false;
+    //whether lines are drawn between the pixels, see draw_grid
+    let mut show_grid = false;
+    //set when the screen has to be drawn again even though the display didn't change
+    let mut redraw = false;
+    //whether a rom is loaded, the web version starts without one
+    let mut loaded = !options.rom.as_os_str().is_empty();
+    //while paused, instructions only run one at a time when stepping
+    let mut paused = options.paused;
+    //whether the rom that's being loaded starts paused, set by holding shift while loading it
+    let mut load_paused = false;
+    //the hexadecimal address typed after pressing G while paused, enter jumps there
+    let mut goto: Option<String> = None;
+    //keypad keys pressed along with control, they stay up for the rom until they are let go
+    let mut shortcut_keys = [false; KEYMAP.len()];
+    c.set_history(HISTORY_LENGTH);
+    let mut beeper = Beeper::new(AudioConfig::default()).await;
+
+    while running {
+        //while an address is typed the keys go to it, escape only cancels it
+        let typing_address = goto.is_some();
+        if let Some(text) = &mut goto {
+            while let Some(character) = get_char_pressed() {
+                if character.is_ascii_hexdigit() && text.len() < 3 {
+                    text.push(character.to_ascii_uppercase());
                 }
             }
-            //6XKK
-            Instruction::LoadRegisterX { x, kk } => {
-                self.registers.set_register(x, kk);
+            if is_key_pressed(KeyCode::Backspace) {
+                text.pop();
+            }
+            if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+                //nothing typed cancels, like escape
+                if let Ok(address) = u16::from_str_radix(text, 16) {
+                    match c.set_program_counter(address) {
+                        Ok(()) => {
+                            if address % 2 == 1 {
+                                eprintln!(
+                                    "warning: {:04X} is odd, instructions normally start at even addresses",
+                                    address
+                                );
+                            }
+                            error = None;
+                        }
+                        Err(out_of_bounds) => eprintln!("cannot jump there: {}", out_of_bounds),
+                    }
+                }
+                goto = None;
+            } else if is_key_pressed(KeyCode::Escape) {
+                goto = None;
             }
-            //7XKK
-            Instruction::AddToRegisterX { x, kk } => {
-                let vx = self.registers.get_register(x);
+        }
 
-                let (tmp, _overflow) = vx.overflowing_add(kk); // as u16 + kk as u16;
-                self.registers.set_register(x, tmp);
+        if is_key_pressed(KeyCode::Escape) && !typing_address {
+            //a rom that is still loading is given up on first, a slow download shouldn't have to
+            //be waited out. Its result is ignored once it arrives, the running rom carries on.
+            if pending_rom.take().is_some() {
+                println!("loading cancelled");
+            } else {
+                running = false;
+                continue;
             }
-            //8xy0
-            Instruction::LoadRegisterXIntoY { x, y } => {
-                let vy = self.registers.get_register(y);
-                self.registers.set_register(x, vy);
+        }
+        if is_key_pressed(KeyCode::F12) {
+            match save_screenshot(c.framebuffer()) {
+                Ok(name) => println!("saved screenshot to {}", name),
+                Err(error) => eprintln!("could not save screenshot: {}", error),
             }
-            //8xy1
-            Instruction::LoadXOrYinX { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
-                self.registers.set_register(x, vx | vy);
+        }
+        if is_key_pressed(KeyCode::F8) && loaded {
+            let start_address = options.start_address.unwrap_or(ROM_START_ADDRESS);
+            match save_disassembly(&current_rom, start_address) {
+                Ok(name) => println!("saved disassembly to {}", name),
+                Err(error) => eprintln!("could not save disassembly: {}", error),
             }
-            //8xy2
-            Instruction::LoadXAndYInX { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
-
-                self.registers.set_register(x, vx & vy);
+        }
+        //s is keypad 8, shortcut_keys keeps it from the rom while control is held
+        if is_key_pressed(KeyCode::S) && is_control_down() && loaded {
+            match save_rom(&current_rom) {
+                Ok(name) => println!("saved rom to {}", name),
+                Err(error) => eprintln!("could not save rom: {}", error),
             }
-            //8xy3
-            Instruction::LoadXXorYInX { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
-                self.registers.set_register(x, vx ^ vy);
+        }
+        if is_key_pressed(KeyCode::P) && !typing_address {
+            println!("{}", state_line(&c));
+        }
+        if is_key_pressed(KeyCode::F9) {
+            match save_memory_dump(&c.memory_dump()) {
+                Ok(name) => println!("saved memory dump to {}", name),
+                Err(error) => eprintln!("could not save memory dump: {}", error),
             }
-            //8xy4
-            Instruction::AddYToX { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
-
-                let (res, fv) = vy.overflowing_add(vx);
-                self.registers.set_register(x, res);
-                self.registers.set_register(0xf, if fv { 1 } else { 0 });
+        }
+        if is_key_pressed(KeyCode::F11) {
+            match recording.take() {
+                Some(frames) => finish_recording(frames),
+                None => {
+                    println!("recording started");
+                    recording = Some(Vec::new());
+                }
             }
+        }
 
-            //8xy5
-            Instruction::SubYFromX { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
+        if is_key_pressed(KeyCode::F10) {
+            fade = match fade {
+                Some(_) => None,
+                None => Some(Fade::new(c.display_dimensions())),
+            };
+            redraw = true;
+        }
+        if is_key_pressed(KeyCode::K) && !typing_address {
+            reduce_flicker = !reduce_flicker;
+            redraw = true;
+        }
+        if is_key_pressed(KeyCode::H) && !typing_address {
+            show_grid = !show_grid;
+        }
 
-                let (res, fv) = vx.overflowing_sub(vy);
-                self.registers.set_register(x, res);
-                self.registers.set_register(0xf, if fv { 0 } else { 1 });
+        //the sound settings, the beep is generated again when they change
+        let mut audio_config = beeper.config();
+        if is_key_pressed(KeyCode::F6) {
+            audio_config.waveform = audio_config.waveform.next();
+        }
+        if is_key_pressed(KeyCode::Minus) {
+            audio_config.volume = (audio_config.volume - VOLUME_STEP).max(0.0);
+        }
+        if is_key_pressed(KeyCode::Equal) {
+            audio_config.volume = (audio_config.volume + VOLUME_STEP).min(1.0);
+        }
+        if is_key_pressed(KeyCode::B) && !typing_address {
+            audio_config.collision_blip = !audio_config.collision_blip;
+        }
+        if audio_config != beeper.config() {
+            println!(
+                "beep: {:?} wave at {:.0}% volume, collision blip {}",
+                audio_config.waveform,
+                audio_config.volume * 100.0,
+                if audio_config.collision_blip {
+                    "on"
+                } else {
+                    "off"
+                }
+            );
+            beeper.set_config(audio_config).await;
+        }
+
+        if is_key_pressed(KeyCode::F7) {
+            show_rom_info = !show_rom_info;
+        }
+        //the quirks change while the rom runs, so their effect shows without a reset
+        if is_control_down() && !typing_address {
+            for (&key, &quirk) in QUIRK_KEYS.iter().zip(Quirk::ALL.iter()) {
+                if is_key_pressed(key) {
+                    let mut quirks = c.quirks();
+                    quirks.set(quirk, !quirks.is_on(quirk));
+                    c.set_quirks(quirks);
+                    println!("quirk {} {}", quirk, on_off(quirks.is_on(quirk)));
+                }
             }
+        }
 
-            //8xy6
-            Instruction::ShiftXRight1 { x } => {
-                let vx = self.registers.get_register(x);
-                let vf = if vx & 1 == 1 { 1 } else { 0 };
+        if is_key_pressed(KeyCode::F3) {
+            //switches between ntsc and pal timing
+            let refresh_rate = if c.refresh_rate() == REFRESH_RATE {
+                PAL_REFRESH_RATE
+            } else {
+                REFRESH_RATE
+            };
+            c.set_refresh_rate(refresh_rate);
+            println!("timers tick at {}Hz", refresh_rate);
+        }
 
-                self.registers.set_register(x, vx.overflowing_shr(1).0);
-                self.registers.set_register(0xF, vf);
-            }
+        if is_key_pressed(KeyCode::F2) {
+            integer_scale = !integer_scale;
+        }
 
-            //8xyE
-            Instruction::ShiftXLeft1 { x } => {
-                let vx = self.registers.get_register(x);
-                let fv = (vx as u16 >> 7) & 1;
-                let res = self.registers.get_register(x).wrapping_shl(1);
+        if is_key_pressed(KeyCode::F1) {
+            speed_meter = match speed_meter {
+                Some(_) => None,
+                None => Some(SpeedMeter::new(&c)),
+            };
+        }
 
-                self.registers.set_register(x, res);
-                self.registers
-                    .set_register(0xf, if fv == 1 { 1 } else { 0 });
+        //the debugger keys aren't part of KEYMAP, so they never reach the keypad
+        if is_key_pressed(KeyCode::Space) && !typing_address {
+            paused = !paused;
+        }
+        if paused && is_key_pressed(KeyCode::G) && loaded && !typing_address {
+            goto = Some(String::new());
+        }
+        //leaving the pause also cancels typing an address
+        if !paused {
+            goto = None;
+        }
+        if paused && !typing_address && is_key_pressed(KeyCode::N) && error.is_none() && loaded {
+            if let Err(stopped) = c.cycle() {
+                eprintln!("program stopped: {}", stopped);
+                error = Some(stopped);
+            }
+        }
+        if paused && !typing_address && is_key_pressed(KeyCode::O) && error.is_none() && loaded {
+            if let Err(stopped) = c.step_over(RUN_UNTIL_LIMIT) {
+                eprintln!("program stopped: {}", stopped);
+                error = Some(stopped);
             }
-            //8xy7
-            Instruction::SubXFromY { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
-                let (res, fv) = vy.overflowing_sub(vx);
-                self.registers.set_register(x, res);
-                self.registers.set_register(0xf, if fv { 0 } else { 1 });
+        }
+        if paused && !typing_address && is_key_pressed(KeyCode::L) && error.is_none() && loaded {
+            if let Err(stopped) = c.run_until(until::blank_screen, RUN_UNTIL_LIMIT) {
+                eprintln!("program stopped: {}", stopped);
+                error = Some(stopped);
             }
+        }
+        //stepping back also undoes the instruction that stopped the program
+        if paused && !typing_address && is_key_pressed(KeyCode::Backspace) && c.step_back() {
+            error = None;
+        }
 
-            //9XY0
-            Instruction::SkipNextInstructionIfXIsNotY { x, y } => {
-                let vx = self.registers.get_register(x);
-                let vy = self.registers.get_register(y);
-                if vx != vy {
-                    self.program_counter += 2;
+        if is_key_pressed(KeyCode::F5) && loaded {
+            c.reset();
+            error = None;
+        }
+        if is_key_pressed(KeyCode::F4) && loaded {
+            let empty = RomBuffer::from_bytes(Vec::new()).expect("an empty rom always fits");
+            //the settings from the command line carry over to the next rom
+            match cpu_builder(empty.clone(), &options) {
+                Ok(mut builder) => {
+                    rom_info = empty.info();
+                    current_rom = empty;
+                    //the window title still names the rom, so this says it's gone
+                    current_name = String::new();
+                    if let Some(trace) = c.take_trace() {
+                        builder = builder.trace(trace);
+                    }
+                    c = builder.build();
+                    c.set_history(HISTORY_LENGTH);
+                    c.set_profiling(options.profile);
+                    for &address in &options.watchpoints {
+                        c.add_watchpoint(address);
+                    }
+                    error = None;
+                    loaded = false;
+                }
+                Err(out_of_bounds) => {
+                    eprintln!("could not unload the rom, {}", out_of_bounds);
+                    error = Some(out_of_bounds.into());
                 }
             }
-            //ANNN
-            Instruction::SetIndexRegister { nnn } => {
-                self.registers.set_index_register(nnn);
-            }
-            //BNNN
-            Instruction::JumpToAddressPlusV0 { nnn } => {
-                let v0 = (self.registers.get_register(0) & 0xf) as u16;
-                self.program_counter = nnn + v0;
-            }
-            //cxkk
-            Instruction::SetXToRandom { x, kk } => {
-                let mut rng = thread_rng();
-                let random_number = rng.gen_range(0..=255);
-                self.registers.set_register(x, random_number & kk);
-            }
-            //DXYN
-            Instruction::DISPLAY { x, y, n } => {
-                //drawing at (start_x, start_y) on the display, wraps around if out of bounds
-                let start_x = (self.registers.get_register(x) % DISPLAY_WIDTH as u8) as usize;
-                let start_y = (self.registers.get_register(y) % DISPLAY_HEIGHT as u8) as usize;
-
-                let sprite_start = self.registers.get_index_register() as usize;
-                self.registers.set_register(0xF, 0);
-
-                //move over all rows of the sprite (it has n rows)
-                for sprite_row in 0..n as usize {
-                    if sprite_start + sprite_row >= RAM_SIZE {
-                        return;
-                    }
-                    let sprite = self.memory.bytes[sprite_start + sprite_row];
-                    for sprite_column in 0..8 as usize {
-                        let pixel_row = start_x + sprite_column;
-                        let pixel_column = start_y + sprite_row;
-
-                        let sprite_pixel_set = sprite >> (7 - sprite_column) & 1 == 1;
+        }
 
-                        //check so as to *not* draw out of bounds of the display
-                        if pixel_row < DISPLAY_WIDTH && pixel_column < DISPLAY_HEIGHT {
-                            if self.display[pixel_column][pixel_row] && sprite_pixel_set {
-                                self.registers.set_register(0xf, 1);
+        //dropping a rom onto the window replaces the running one once it's loaded
+        for file in get_dropped_files() {
+            pending_name = rom_name(file.path.as_deref().unwrap_or(std::path::Path::new("")));
+            pending_rom = Some(load_dropped_file(file));
+            load_paused = is_shift_down();
+            load_error = None;
+        }
+        //pasting a url downloads the rom behind it, v is keypad F but shortcut_keys keeps it from the rom
+        #[cfg(not(target_arch = "wasm32"))]
+        if is_key_pressed(KeyCode::V) && is_control_down() {
+            match miniquad::window::clipboard_get() {
+                Some(url) if is_url(url.trim()) => {
+                    pending_name = rom_name(std::path::Path::new(url.trim()));
+                    pending_rom = Some(load_url(url.trim().to_string()));
+                    load_paused = is_shift_down();
+                    load_error = None;
+                }
+                _ => load_error = Some(RomError::NotARom(String::from("the pasted text"))),
+            }
+        }
+        if let Some(receiver) = &pending_rom {
+            match receiver.try_recv() {
+                Ok(Ok(rom)) => {
+                    pending_rom = None;
+                    match cpu_builder(rom.clone(), &options) {
+                        Ok(mut builder) => {
+                            rom_info = rom.info();
+                            current_rom = rom;
+                            current_name = core::mem::take(&mut pending_name);
+                            //the trace carries on into the new rom
+                            if let Some(trace) = c.take_trace() {
+                                builder = builder.trace(trace);
                             }
-                            self.display[pixel_column][pixel_row] ^= sprite_pixel_set;
+                            c = builder.build();
+                            c.set_history(HISTORY_LENGTH);
+                            c.set_profiling(options.profile);
+                            for &address in &options.watchpoints {
+                                c.add_watchpoint(address);
+                            }
+                            error = None;
+                            loaded = true;
+                            paused = load_paused;
+                        }
+                        Err(out_of_bounds) => {
+                            eprintln!("rom does not fit, {}", out_of_bounds);
+                            error = Some(out_of_bounds.into());
                         }
                     }
                 }
-            }
-            //exa1
-            Instruction::SkipIfVxNotPressed { x } => {
-                let key = CPU::u8_to_keycode(self.registers.get_register(x) & 0xf);
-                if !is_key_down(key) {
-                    self.program_counter += 2;
-                }
-            }
-            //ex9e
-            Instruction::SkipIfVxPressed { x } => {
-                let key = CPU::u8_to_keycode(self.registers.get_register(x) & 0xf);
-                if is_key_down(key) {
-                    self.program_counter += 2;
+                Ok(Err(error)) => {
+                    pending_rom = None;
+                    eprintln!("{}", error);
+                    load_error = Some(error);
                 }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => pending_rom = None,
             }
-            //fx0a
-            Instruction::WaitForKeyPressed { x } => {
-                let key = CPU::u8_to_keycode(self.registers.get_register(x) & 0xf);
-                //wait for when a key is 
-                //while !is_key_down(key) {}
-            }
-            //fx07
-            Instruction::SetXToDelayTimer { x } => {
-                let vdt = self.registers.get_delay_timer();
-                self.registers.set_register(x, vdt);
-            }
-            //fx15
-            Instruction::SetDelayTimerToX { x } => {
-                let vx = self.registers.get_register(x);
-                self.registers.set_delay_timer(vx);
-            }
-            Instruction::SetSoundTimerToX { x } => {
-                let vx = self.registers.get_register(x);
-                self.registers.set_sound_timer(vx);
-            }
-            //fx1E
-            Instruction::AddXtoI { x } => {
-                let vx = self.registers.get_register(x) as u16;
-                let vi = self.registers.get_index_register();
-                let added = vi + vx;
+        }
 
-                self.registers.set_index_register(added);
-            }
-            //fx29
-            Instruction::SetIToSpriteX { x } => {
-                let vx = (self.registers.get_register(x) * 5) as u16 & 0xffff;
-                //the sprite at *index* x, not location x.
-                self.registers.set_index_register(vx);
-            }
-            Instruction::LoadBCDOfX { x } => {
-                let vx = self.registers.get_register(x);
-                let store_index = self.registers.get_index_register() as usize;
-                self.memory.bytes[store_index] = vx / 100;
-                self.memory.bytes[store_index + 1] = (vx % 100) / 10;
-                self.memory.bytes[store_index + 2] = (vx % 100) % 10;
-            }
-            //fx55
-            Instruction::Write0ThroughX { x } => {
-                let vi = self.registers.get_index_register() as usize;
+        //every key is polled each frame, so a key let go while the window was in the background or
+        //while another rom loaded can't get stuck, and Cpu::release_all_keys isn't needed here.
+        //control turns keypad keys into shortcuts, ctrl+1 should toggle a quirk without pressing keypad 1
+        let control = is_control_down();
+        for (key, keycode) in KEYMAP.iter().enumerate() {
+            let down = is_key_down(*keycode);
+            shortcut_keys[key] = down && (control || shortcut_keys[key]);
+            c.set_key_state(key as u8, down && !shortcut_keys[key]);
+        }
 
-                for register in 0..x + 1 {
-                    let register_value = self.registers.get_register(register);
-                    self.memory.bytes[vi + register as usize] = register_value;
-                }
+        if error.is_none() && loaded && !paused {
+            //holding tab fast-forwards, the timers speed up along with the instructions
+            let speed = if is_key_down(KeyCode::Tab) {
+                TURBO_SPEED
+            } else {
+                1.0
+            };
+            if let Err(stopped) = c.step_for(Duration::from_secs_f32(get_frame_time() * speed)) {
+                eprintln!("program stopped: {}", stopped);
+                error = Some(stopped);
             }
-            //fx65
-            Instruction::Load0ThroughX { x } => {
-                let vi = self.registers.get_index_register() as usize;
-                for i in 0..x + 1 {
-                    self.registers
-                        .set_register(i, self.memory.bytes[vi + i as usize]);
-                }
+            if let Some(hit) = c.watchpoint_hit() {
+                println!("watchpoint: {}", hit);
+                paused = true;
+            }
+            //pausing leaves the opcode on screen and lets the instructions before it be undone
+            if let Some(unknown) = c.unknown_opcode() {
+                eprintln!("program stopped: {}", unknown);
+                paused = true;
             }
         }
-    }
-    //returns the first 4 bits of the opcode as a byte
-    fn first_nibble(&self, opcode: u16) -> u8 {
-        ((opcode >> 12) & 0xF) as u8
-    }
-    //returns the second 4 bits of the opcode as a byte
-    fn second_nibble(&self, opcode: u16) -> u8 {
-        ((opcode >> 8) & 0xf) as u8
-    }
-    fn third_nibble(&self, opcode: u16) -> u8 {
-        ((opcode >> 4) & 0xf) as u8
-    }
-    fn fourth_nibble(&self, opcode: u16) -> u8 {
-        (opcode as u8) & 0xf
-    }
-    //returns the last byte
-    fn last_byte(&self, code: u16) -> u8 {
-        (code & 0xff) as u8
-    }
-    fn oxxx(&self, code: u16) -> u16 {
-        code & 0xfff
-    }
-
-    fn cycle(&mut self) {
-        let opcode = self.fetch(&self.memory);
-
-        self.program_counter += 2;
-
-        let instruction = self.decode(opcode);
-
-        self.execute(instruction);
-
-        self.registers.decrement_sound_timer();
-        self.registers.decrement_delay_timer();
-    }
-
-    fn new(rom: RomBuffer) -> Self {
-        let mut memory = RAM::with_fonts();
-        for (x, y) in rom.buffer.iter().enumerate() {
-            memory.bytes[0x200 + x] = *y;
-        }
-
-        Self {
-            display: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-            program_counter: 0x200,
-            registers: Registers::new(),
-            memory: memory,
-            stack: Stack::new(),
-            stackpointer: 0,
-        }
-    }
-}
-
-///A list of every instruction in the chip8 language
-///nnn is a hexadecimal memory address, it's 12 bits long
-///nn is a hexadecimal byte, it's 8 bits
-///n is what's called a "nibble", it's 4 bits
-///X and Y are registers
-enum Instruction {
-    NOOP,                 //0nnn
-    ClearScreen,          //00e0
-    ReturnFromSubroutine, //00ee
-    JUMP { nnn: u16 },    //1nnn where nnn is a 12 bit value (lowest 12 bits of the instruction)
-    AddToRegisterX { x: u8, kk: u8 },
-    CallSubroutineAtNNN { nnn: u16 },
-    LoadRegisterX { x: u8, kk: u8 }, //6xkk puts the value kk into Vx
-    LoadXOrYinX { x: u8, y: u8 },    //8xy1
-    LoadXAndYInX { x: u8, y: u8 },   //8xy2
-    LoadXXorYInX { x: u8, y: u8 },   //8xy3
-    AddYToX { x: u8, y: u8 },        //8xy4
-    SubYFromX { x: u8, y: u8 },      //8xy5
-    ShiftXRight1 { x: u8 },          //8xy6
-    ShiftXLeft1 { x: u8 },           //8xyE
-    SubXFromY { x: u8, y: u8 },      //8xy7
-    LoadRegisterXIntoY { x: u8, y: u8 }, //Stores the value of register Vy in register Vx
-    SetIndexRegister { nnn: u16 },   //ANNN set index register I to nnn
-    JumpToAddressPlusV0 { nnn: u16 }, //BNNN jump to address nnn + v0
-    SkipNextInstructionIfXIsKK { x: u8, kk: u8 }, //skips the next instruction only if the register X holds the value kk
-    SkipNextInstructionIfXIsNotKK { x: u8, kk: u8 }, //same as previous, except skips if register x does not hold value kk
-    SkipNextInstructionIfXIsY { x: u8, y: u8 },
-    SkipNextInstructionIfXIsNotY { x: u8, y: u8 },
-    SetXToRandom { x: u8, kk: u8 },  //cxkk
-    DISPLAY { x: u8, y: u8, n: u8 }, //DXYN draws a sprite at coordinate from vx and vy, of width 8 and height n
-    SkipIfVxNotPressed { x: u8 },    //exa1
-    SkipIfVxPressed { x: u8 },       //ex9e
-    WaitForKeyPressed { x: u8 },      //fx0a
-    SetXToDelayTimer { x: u8 },      //fx07
-    SetDelayTimerToX { x: u8 },      //Fx15
-    SetSoundTimerToX { x: u8 },      //fx18
-    AddXtoI { x: u8 },               //fx1e
-    SetIToSpriteX { x: u8 },         //fx29
-    LoadBCDOfX { x: u8 },            //fx33
-    Write0ThroughX { x: u8 },        //fx55
-    Load0ThroughX { x: u8 },         //fx65
-}
-
-#[macroquad::main("Chip 8 interpreter \"Chippie\" ")]
-async fn main() {
-    //creating a chip8 cpu object with a rom loaded
-    let b = RomBuffer::new("./pong.ch8");
-    let mut c = CPU::new(b);
 
-    //used for
-    let mut image = Image::gen_image_color(DISPLAY_WIDTH as u16, DISPLAY_HEIGHT as u16, WHITE);
-    let mut buffer = vec![false; DISPLAY_WIDTH * DISPLAY_HEIGHT];
-    let texture = Texture2D::from_image(&image);
-    texture.set_filter(FilterMode::Nearest);
-    let mut running = true;
-
-    while running {
-        if is_key_pressed(KeyCode::Escape) {
-            running = false;
-            continue;
+        beeper.set_pattern(c.audio_pattern(), c.pitch()).await;
+        beeper.update(c.is_beeping() && loaded && !paused && error.is_none());
+        if c.take_collision() {
+            beeper.collision();
         }
 
-        for _ in 0..=CYCLES_PER_FRAME {
-            c.cycle();
+        if let Some(frames) = &mut recording {
+            frames.push(c.framebuffer().clone());
+            if frames.len() >= MAX_RECORDED_FRAMES {
+                finish_recording(recording.take().unwrap_or_default());
+            }
         }
 
-        clear_background(WHITE);
-        for y in 0..DISPLAY_HEIGHT as i32 {
-            for x in 0..DISPLAY_WIDTH as i32 {
-                buffer[y as usize * DISPLAY_WIDTH + x as usize] = c.display[y as usize][x as usize];
+        //the borders around an integer scaled display are left black
+        clear_background(if integer_scale { BLACK } else { WHITE });
+        //00FE and 00FF change the size of the display, the texture and fading follow along
+        let dimensions = c.display_dimensions();
+        if dimensions != (image.width as usize, image.height as usize) {
+            image = Image::gen_image_color(dimensions.0 as u16, dimensions.1 as u16, WHITE);
+            texture = Texture2D::from_image(&image);
+            texture.set_filter(FilterMode::Nearest);
+            if fade.is_some() {
+                fade = Some(Fade::new(dimensions));
             }
+            redraw = true;
         }
-        for i in 0..buffer.len() {
-            image.set_pixel(
-                (i % DISPLAY_WIDTH) as u32,
-                (i / DISPLAY_WIDTH) as u32,
-                match buffer[i as usize] {
-                    true => BLACK,
-                    false => WHITE,
-                },
-            );
+        //the texture only needs new pixels when the display changed, or while pixels fade out
+        //with flicker reduction the previous frame changes at every vblank, so it is redrawn too
+        if let Some(fade) = &mut fade {
+            c.take_display_dirty();
+            fade.update(&shown_framebuffer(&c, reduce_flicker));
+            image.bytes = fade.to_rgba(FOREGROUND, BACKGROUND);
+            texture.update(&image);
+        } else if redraw || reduce_flicker {
+            c.take_display_dirty();
+            let fb = shown_framebuffer(&c, reduce_flicker);
+            image.bytes = framebuffer_to_rgba(&fb, FOREGROUND, BACKGROUND);
+            texture.update(&image);
+        } else {
+            //only the rows that changed are converted again
+            let mut changed = false;
+            for row in c.take_dirty_rows() {
+                let start = row * dimensions.0 * 4;
+                let pixels = image.bytes[start..start + dimensions.0 * 4].chunks_exact_mut(4);
+                for (x, pixel) in pixels.enumerate() {
+                    let on = c.framebuffer().is_on(x, row);
+                    pixel.copy_from_slice(if on { &FOREGROUND } else { &BACKGROUND });
+                }
+                changed = true;
+            }
+            if changed {
+                texture.update(&image);
+            }
         }
+        redraw = false;
 
-        texture.update(&image);
-
+        let (position, size) = display_area(integer_scale, dimensions);
         draw_texture_ex(
             &texture,
-            0.0,
-            0.0,
+            position.x,
+            position.y,
             WHITE,
             DrawTextureParams {
-                dest_size: Some(vec2(screen_width(), screen_height())),
+                dest_size: Some(size),
                 ..Default::default()
             },
         );
+        if show_grid {
+            draw_grid(position, size, dimensions);
+        }
+        if pending_rom.is_some() {
+            draw_text(
+                "loading... escape cancels",
+                10.0,
+                screen_height() - 10.0,
+                30.0,
+                RED,
+            );
+        } else if let Some(load_error) = &load_error {
+            draw_text(
+                &load_error.to_string(),
+                10.0,
+                screen_height() - 10.0,
+                30.0,
+                RED,
+            );
+        } else if current_name != title_name {
+            let name = if current_name.is_empty() {
+                "no rom loaded"
+            } else {
+                &current_name
+            };
+            draw_text(name, 10.0, screen_height() - 10.0, 20.0, GRAY);
+        }
+        //messages go below the speed overlay when it's shown
+        let mut message_y = 30.0;
+        if let Some(speed_meter) = &mut speed_meter {
+            speed_meter.update(&c);
+            let speed = format!(
+                "{} fps, {} instructions/s, timers at {}Hz",
+                get_fps(),
+                speed_meter.instructions_per_second,
+                c.refresh_rate()
+            );
+            draw_text(&speed, 10.0, 20.0, 20.0, GREEN);
+            message_y = 50.0;
+        }
+        if paused {
+            let next = match c.peek_instruction() {
+                Some(instruction) => instruction.to_string(),
+                None => String::from("not an instruction"),
+            };
+            let status = format!("paused, next: {:04X}  {}", c.program_counter(), next);
+            draw_text(&status, 10.0, message_y, 30.0, BLUE);
+            message_y += 30.0;
+            draw_memory_map(&c);
+        }
+        if let Some(hit) = c.watchpoint_hit() {
+            let status = format!("watchpoint: {}", hit);
+            draw_text(&status, 10.0, message_y, 30.0, RED);
+            message_y += 30.0;
+        }
+        if let Some(text) = &goto {
+            let prompt = format!("go to address: {}_", text);
+            draw_text(&prompt, 10.0, message_y, 30.0, BLUE);
+            message_y += 30.0;
+        }
+        if let Some(stopped) = error {
+            draw_text(&stopped.to_string(), 10.0, message_y, 30.0, RED);
+            message_y += 30.0;
+        } else if let Some(unknown) = c.unknown_opcode() {
+            draw_text(&unknown.to_string(), 10.0, message_y, 30.0, RED);
+            message_y += 30.0;
+        } else if c.is_halted() {
+            draw_text("program exited", 10.0, message_y, 30.0, RED);
+            message_y += 30.0;
+        }
+        if show_rom_info {
+            for line in rom_info.to_string().lines() {
+                draw_text(line, 10.0, message_y, 20.0, BLUE);
+                message_y += 20.0;
+            }
+        }
+        if is_control_down() {
+            let quirks = c.quirks();
+            for (index, &quirk) in Quirk::ALL.iter().enumerate() {
+                let line = format!(
+                    "ctrl+{} {}: {}",
+                    index + 1,
+                    quirk,
+                    on_off(quirks.is_on(quirk))
+                );
+                draw_text(&line, 10.0, message_y, 20.0, BLUE);
+                message_y += 20.0;
+            }
+        }
+
+        //nothing moves while the emulator isn't running, so it draws fewer frames to leave the
+        //computer idle, hotkeys are still read every frame
+        #[cfg(not(target_arch = "wasm32"))]
+        if (paused || !loaded || error.is_some() || c.is_halted())
+            && pending_rom.is_none()
+            && recording.is_none()
+        {
+            std::thread::sleep(IDLE_FRAME_TIME);
+        }
 
         next_frame().await;
     }
+
+    if options.dump {
+        print!("{}", render_ascii(c.framebuffer()));
+    }
+    if options.profile {
+        print_profile(&c);
+    }
+}
+
+///The registers, program counter, I, stack pointer and timers on a single line, like
+///`PC=0204 I=0300 SP=0 V0=05 ... VF=00 DT=00 ST=00`. The format stays the same, so the output
+///can be grepped.
+fn state_line(c: &Cpu) -> String {
+    let mut line = format!(
+        "PC={:04X} I={:04X} SP={:X}",
+        c.program_counter(),
+        c.index_register(),
+        c.stack_pointer()
+    );
+    for register in 0..NUM_REGISTERS as u8 {
+        let value = c.get_register(register).unwrap_or_default();
+        line.push_str(&format!(" V{:X}={:02X}", register, value));
+    }
+    line.push_str(&format!(
+        " DT={:02X} ST={:02X}",
+        c.delay_timer(),
+        c.sound_timer()
+    ));
+    line
+}
+
+///Prints how often each kind of instruction ran, most frequent first
+fn print_profile(c: &Cpu) {
+    let report = c.profile_report();
+    let total: u64 = report.iter().map(|(_, count)| count).sum();
+    for (kind, count) in report {
+        let share = count as f64 / total as f64 * 100.0;
+        println!("{:>10} {:>5.1}%  {:?}", count, share, kind);
+    }
 }
@@ -0,0 +1,63 @@
+//! Runs the test roms in the repository and compares the screen they end on with the one checked
+//! in under tests/screens. The flags and opcode tests draw a check mark or an error for every
+//! case, so a changed screen means an instruction started behaving differently.
+use chip8_emulator::{render_ascii, CpuBuilder, FrameOutcome, Quirks, RomBuffer};
+
+///Enough frames for every rom here to finish drawing, with the vblank quirk a frame draws one sprite
+const FRAMES: usize = 3000;
+///About the 500 instructions per second of a cosmac vip at 60 frames a second
+const INSTRUCTIONS_PER_FRAME: usize = 9;
+
+///The screen the rom shows after FRAMES frames, run with the cosmac vip quirks and a fixed seed
+fn final_screen(rom: &[u8]) -> String {
+    let rom = RomBuffer::from_bytes(rom.to_vec()).unwrap();
+    let mut cpu = CpuBuilder::new(rom).quirks(Quirks::chip8()).seed(0).build();
+    for _ in 0..FRAMES {
+        if cpu.run_frame(INSTRUCTIONS_PER_FRAME).unwrap() == FrameOutcome::Halted {
+            break;
+        }
+    }
+    render_ascii(cpu.framebuffer())
+}
+
+fn assert_screen(rom: &[u8], screen: &str) {
+    let actual = final_screen(rom);
+    assert!(
+        actual == screen,
+        "the rom ended on\n{}instead of\n{}",
+        actual,
+        screen
+    );
+}
+
+#[test]
+fn ibm_logo() {
+    assert_screen(
+        include_bytes!("../ibmlogo.ch8"),
+        include_str!("screens/ibmlogo.txt"),
+    );
+}
+
+#[test]
+fn test_logo() {
+    assert_screen(
+        include_bytes!("../testlogo.ch8"),
+        include_str!("screens/testlogo.txt"),
+    );
+}
+
+#[test]
+fn corax_opcode_test() {
+    assert_screen(
+        include_bytes!("../testrom.ch8"),
+        include_str!("screens/testrom.txt"),
+    );
+}
+
+#[test]
+fn flags_test() {
+    assert_screen(
+        include_bytes!("../flagstest.ch8"),
+        include_str!("screens/flagstest.txt"),
+    );
+}